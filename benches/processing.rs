@@ -0,0 +1,69 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::str::FromStr;
+
+use accounter::in_memory_ledger::InMemoryLedger;
+use accounter::*;
+
+const RECORDS: usize = 100_000;
+
+fn amount_parsing(c: &mut Criterion) {
+    let amounts = [
+        "0",
+        "1.5",
+        "+6.0",
+        "1234567890.1234",
+        "922337203685477.5807",
+    ];
+    let mut group = c.benchmark_group("amount");
+    group.throughput(Throughput::Elements(amounts.len() as u64));
+    group.bench_function("from_str", |b| {
+        b.iter(|| {
+            for amount in amounts {
+                black_box(Amount::from_str(black_box(amount)).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn line_parsing(c: &mut Criterion) {
+    let input = synthetic_csv(RECORDS);
+    let options = CsvOptions::default();
+    let mut group = c.benchmark_group("csv");
+    group.throughput(Throughput::Elements(RECORDS as u64));
+    group.bench_function("parse_csv_line", |b| {
+        b.iter(|| {
+            for line in input.lines().skip(1) {
+                black_box(parse_csv_line(black_box(line), &options).unwrap());
+            }
+        })
+    });
+    group.finish();
+}
+
+fn processing(c: &mut Criterion) {
+    let input = synthetic_csv(RECORDS);
+    let runtime = tokio::runtime::Runtime::new().unwrap();
+    let mut group = c.benchmark_group("csv");
+    group.throughput(Throughput::Elements(RECORDS as u64));
+    group.sample_size(10);
+    group.bench_function("process_csv", |b| {
+        b.iter(|| {
+            runtime.block_on(async {
+                let mut summary = Vec::<u8>::new();
+                process_csv(
+                    AccountHub::new(|_client_id| InMemoryLedger::connect()),
+                    input.as_bytes(),
+                    &mut summary,
+                )
+                .await
+                .unwrap();
+                black_box(summary)
+            })
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, amount_parsing, line_parsing, processing);
+criterion_main!(benches);
@@ -1,7 +1,11 @@
+use std::collections::VecDeque;
 use std::error::Error;
 use std::fmt;
 
+use serde::Deserialize;
+
 pub use crate::ledger::*;
+use crate::ClientId;
 
 #[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
 pub enum Transaction {
@@ -12,15 +16,69 @@ pub enum Transaction {
 }
 
 /// List of account manipulation actions
-#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialEq, Eq, Clone, Hash)]
 pub enum Action {
-    Transact((TransactionId, Transaction)),
+    /// A deposit or withdrawal with its optional external reference, which is stored in the ledger
+    /// with the transaction (it does not affect the balances, see 'Reference')
+    Transact((TransactionId, Transaction), Option<String>),
     Dispute(TransactionId),
     Resolve(TransactionId),
     ChargeBack(TransactionId),
+    /// Withdrawal which is executed only if the available funds are still the expected ones
+    /// (for optimistic concurrency control)
+    ConditionalWithdraw {
+        id: TransactionId,
+        amount: Amount,
+        expected_available: Amount,
+    },
+    /// Reverses a deposit which was posted in error (e.g. a duplicate import), without dispute workflow
+    ReverseDeposit(TransactionId),
+    /// Moves funds from one client to another, coordinated by 'AccountHub'
+    /// (recorded as a withdrawal at the source and a deposit at the destination with the same id)
+    Transfer {
+        id: TransactionId,
+        from: ClientId,
+        to: ClientId,
+        amount: Amount,
+    },
+    /// Wipes the account back to the starting state, clearing its ledger (an admin action for
+    /// test/staging environments, ignored unless compiled with the "dangerous-admin" feature)
+    Reset,
+}
+
+impl Action {
+    /// The id of the transaction the action creates or refers to (None for 'Reset')
+    pub fn transaction_id(&self) -> Option<TransactionId> {
+        match *self {
+            Action::Transact((id, _), _)
+            | Action::Dispute(id)
+            | Action::Resolve(id)
+            | Action::ChargeBack(id)
+            | Action::ConditionalWithdraw { id, .. }
+            | Action::ReverseDeposit(id)
+            | Action::Transfer { id, .. } => Some(id),
+            Action::Reset => None,
+        }
+    }
+
+    /// A simple estimate of the processing cost of the action (for queue accounting):
+    /// * 2 for the ones creating a new transaction in the ledger with its amount (transacts)
+    /// * 1 for the ones only changing the state of an existing transaction (disputes, resolves, charge backs, reversals)
+    pub fn weight(&self) -> usize {
+        match self {
+            Action::Transact(..) | Action::ConditionalWithdraw { .. } | Action::Transfer { .. } => {
+                2
+            }
+            Action::Dispute(_)
+            | Action::Resolve(_)
+            | Action::ChargeBack(_)
+            | Action::ReverseDeposit(_)
+            | Action::Reset => 1,
+        }
+    }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransactionError {
     /// try to access locked account
     AccountLocked,
@@ -28,18 +86,42 @@ pub enum TransactionError {
     InvalidAmount,
     /// can not book that much amount
     WouldOverFlow,
+    /// the total funds would exceed the maximum balance of the account (see 'AccountPolicy::max_balance')
+    ExceedsBalanceCap,
+    /// the available funds are not enough (only if negative available funds are forbidden)
+    InsufficientFunds,
     /// resolve/charge back needs open dispute first
     DisputeNotOpenedYet,
     /// a dispute already opened with the given transaction id
     AlreadyInDispute,
     /// already charged back
     AlreadyChargedBack,
+    /// the deposit is already reversed
+    AlreadyReversed,
+    /// the deposit is older than the dispute window
+    DisputeWindowExpired,
+    /// the source and the destination of a transfer are the same
+    SelfTransfer,
+    /// the source of a transfer is not the client executing it
+    TransferSourceMismatch,
+    /// the ledger can not remove the transactions of the account (see 'Action::Reset')
+    ResetUnsupported,
+    /// the external reference of the transaction is empty or longer than 'Reference::MAX_LEN' bytes
+    InvalidReference,
+    /// the ledger of the account is already closed (see 'Account::close')
+    LedgerClosed,
+    /// the source of a transfer was debited, but the destination could not be credited
+    TransferPartiallyFailed,
     /// there is no such transaction in the ledger
     InvalidTransactionId,
+    /// the transaction belongs to another account sharing the ledger (see 'AccountPolicy::check_transaction_owner')
+    NotTransactionOwner,
     /// based on assumption that withdrawals can not be disputed
     InvalidTransactionType,
     /// this check is theoretically not needed (unique TransactionIds guaranteed in specification)
     RepeatedTransactionId,
+    /// the available funds differ from the expected ones of a conditional withdrawal
+    PreconditionFailed,
     /// a ledger real DB would have possible access errors
     DbError,
     /// this should never happen
@@ -52,12 +134,24 @@ impl fmt::Display for TransactionError {
             TransactionError::AccountLocked => "try to access locked account",
             TransactionError::InvalidAmount => "zero or negative transaction amount",
             TransactionError::WouldOverFlow => "can not book that much amount",
+            TransactionError::ExceedsBalanceCap => "the total funds would exceed the maximum balance",
+            TransactionError::InsufficientFunds => "the available funds are not enough",
             TransactionError::DisputeNotOpenedYet => "resolve/charge back needs open dispute first",
             TransactionError::AlreadyInDispute => "a dispute already opened with the given transaction id",
             TransactionError::AlreadyChargedBack => "already charged back",
+            TransactionError::AlreadyReversed => "the deposit is already reversed",
+            TransactionError::DisputeWindowExpired => "the deposit is older than the dispute window",
+            TransactionError::SelfTransfer => "the source and the destination of a transfer are the same",
+            TransactionError::TransferSourceMismatch => "the source of a transfer is not the executing client",
+            TransactionError::ResetUnsupported => "the ledger can not remove the transactions of the account",
+            TransactionError::InvalidReference => "the external reference of the transaction is empty or too long",
+            TransactionError::LedgerClosed => "the ledger of the account is already closed",
+            TransactionError::TransferPartiallyFailed => "the source of a transfer was debited, but the destination could not be credited",
             TransactionError::InvalidTransactionId => "there is no such transaction in the ledger",
+            TransactionError::NotTransactionOwner => "the transaction belongs to another account",
             TransactionError::InvalidTransactionType => "based on assumption that withdrawals can not be disputed",
             TransactionError::RepeatedTransactionId => "this check is theoretically not needed (unique TransactionIds guaranteed in specification)",
+            TransactionError::PreconditionFailed => "the available funds differ from the expected ones",
             TransactionError::DbError => "a ledger real DB would have possible access errors",
             TransactionError::Unexpected => "this should have never happened",
         };
@@ -67,12 +161,99 @@ impl fmt::Display for TransactionError {
 
 impl Error for TransactionError {}
 
+impl TransactionError {
+    /// Whether the error is transient, so the same action may succeed if it is retried
+    /// (logical errors would be refused again)
+    pub fn retryable(&self) -> bool {
+        match self {
+            TransactionError::DbError => true,
+            TransactionError::AccountLocked
+            | TransactionError::InvalidAmount
+            | TransactionError::WouldOverFlow
+            | TransactionError::ExceedsBalanceCap
+            | TransactionError::InsufficientFunds
+            | TransactionError::DisputeNotOpenedYet
+            | TransactionError::AlreadyInDispute
+            | TransactionError::AlreadyChargedBack
+            | TransactionError::AlreadyReversed
+            | TransactionError::DisputeWindowExpired
+            | TransactionError::SelfTransfer
+            | TransactionError::TransferSourceMismatch
+            | TransactionError::ResetUnsupported
+            | TransactionError::InvalidReference
+            | TransactionError::LedgerClosed
+            | TransactionError::TransferPartiallyFailed
+            | TransactionError::InvalidTransactionId
+            | TransactionError::NotTransactionOwner
+            | TransactionError::InvalidTransactionType
+            | TransactionError::RepeatedTransactionId
+            | TransactionError::PreconditionFailed
+            | TransactionError::Unexpected => false,
+        }
+    }
+}
+
+/// How to answer a dispute on a transaction which is already in dispute
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DuplicateDisputePolicy {
+    /// refuse it with 'AlreadyInDispute'
+    #[default]
+    Error,
+    /// accept it without any change (the dispute is open anyway)
+    Idempotent,
+}
+
+/// Optional behaviours of an Account (all of them are turned off by default)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AccountPolicy {
+    /// Withdrawals exceeding the available funds are not refused, but recorded as pending,
+    /// and executed later (in their original order) when further funds become available.
+    pub pending_withdrawals: bool,
+    /// Handling of repeated disputes on the same transaction
+    pub duplicate_dispute: DuplicateDisputePolicy,
+    /// Disputes are refused if holding the funds would make the available funds negative
+    pub forbid_negative_available: bool,
+    /// Deposits can be disputed only until this many further actions were executed on the account
+    /// (counted in successful actions of any kind, see 'Account::transaction_count')
+    pub dispute_window: Option<u64>,
+    /// Deposits (and incoming transfers) are refused if the total funds would exceed this cap
+    #[serde(deserialize_with = "crate::amount::deserialize_optional_amount")]
+    pub max_balance: Option<Amount>,
+    /// Disputes, resolves, charge backs and reversals are refused with 'NotTransactionOwner' for the
+    /// transactions not created by this account (needed if the ledger is shared by several accounts),
+    /// based on the owner recorded in the ledger (see 'Account::with_owner')
+    pub check_transaction_owner: bool,
+    /// Keeps the amounts of the accepted deposits in memory, so their median can be calculated
+    /// (see 'Account::deposit_stats')
+    pub buffer_deposit_amounts: bool,
+}
+
+fn empty_deposit_stats(policy: &AccountPolicy) -> AmountStats {
+    if policy.buffer_deposit_amounts {
+        AmountStats::buffered()
+    } else {
+        AmountStats::default()
+    }
+}
+
 #[derive(Debug)]
 pub struct Account<L> {
     total: Amount,
     held: Amount,
     locked: bool,
+    transaction_count: u64,
+    policy: AccountPolicy,
+    /// withdrawals waiting for funds, in the order of their arrival
+    pending: VecDeque<(TransactionId, Amount, TransactionInfo)>,
+    /// the client of the account, recorded as the owner of its transactions
+    owner: Option<ClientId>,
+    /// statistics of the accepted deposits
+    deposits: AmountStats,
     ledger: L,
+    /// set by 'close', the ledger is not accessed any more
+    closed: bool,
 }
 
 impl<L> Account<L>
@@ -81,11 +262,31 @@ where
 {
     /// Creates a not locked account with zero balance.
     pub fn new(ledger: L) -> Self {
+        Account::with_policy(ledger, AccountPolicy::default())
+    }
+
+    /// Creates a not locked account with zero balance, which behaves according to the given policy.
+    pub fn with_policy(ledger: L, policy: AccountPolicy) -> Self {
         Account {
             total: Amount::ZERO,
             held: Amount::ZERO,
             locked: false,
+            transaction_count: 0,
+            policy,
+            pending: VecDeque::new(),
+            owner: None,
+            deposits: empty_deposit_stats(&policy),
             ledger,
+            closed: false,
+        }
+    }
+
+    /// Records the given client as the owner of the transactions created by the account
+    /// (needed by 'AccountPolicy::check_transaction_owner' on a ledger shared by several accounts)
+    pub fn with_owner(self, owner: ClientId) -> Self {
+        Account {
+            owner: Some(owner),
+            ..self
         }
     }
 
@@ -109,13 +310,70 @@ where
         self.locked
     }
 
+    /// The number of successfully executed actions (of any kind)
+    pub fn transaction_count(&self) -> u64 {
+        self.transaction_count
+    }
+
+    /// Statistics of the accepted deposits (including the later disputed or reversed ones)
+    pub fn deposit_stats(&self) -> &AmountStats {
+        &self.deposits
+    }
+
+    /// The external reference of the transaction (if it was given)
+    pub async fn reference(
+        &self,
+        id: TransactionId,
+    ) -> Result<Option<Reference>, TransactionError> {
+        self.transaction_state(id)
+            .await
+            .map(|state| state.and_then(|state| state.info().reference))
+    }
+
+    /// The state of the transaction as recorded in the ledger (None for unknown ids)
+    pub async fn transaction_state(
+        &self,
+        id: TransactionId,
+    ) -> Result<Option<TransactionState>, TransactionError> {
+        self.check_open()?;
+        self.ledger
+            .get(id)
+            .await
+            .map_err(|_| TransactionError::DbError)
+    }
+
+    fn check_open(&self) -> Result<(), TransactionError> {
+        match self.closed {
+            true => Err(TransactionError::LedgerClosed),
+            false => Ok(()),
+        }
+    }
+
+    /// The total of withdrawals waiting for funds (always zero without the pending withdrawals policy)
+    pub fn pending(&self) -> Amount {
+        self.pending
+            .iter()
+            .try_fold(Amount::ZERO, |sum, (_id, amount, _info)| {
+                Amount::checked_add(sum, *amount)
+            })
+            .unwrap_or(Amount::MAX)
+    }
+
     /// Deposit/Withdraw funds to/from the account
+    /// (the reference is stored in the same ledger entry as the transaction)
     /// REQUIRES: unique TransactionIds (guaranteed in specification)
     async fn transact(
         &mut self,
         id: TransactionId,
         transaction: Transaction,
+        reference: Option<&str>,
     ) -> Result<(), TransactionError> {
+        let reference = match reference {
+            Some(reference) => {
+                Some(Reference::new(reference).ok_or(TransactionError::InvalidReference)?)
+            }
+            None => None,
+        };
         if self.is_locked() {
             return Err(TransactionError::AccountLocked); //TODO ASK! should we allow deposit in this case?
         }
@@ -126,35 +384,56 @@ where
             _ => {}
         }
 
+        let info = TransactionInfo {
+            reference,
+            owner: self.owner,
+            ..TransactionInfo::default()
+        };
         match transaction {
             Transaction::Deposit(amount) => {
                 if amount <= Amount::ZERO {
                     return Err(TransactionError::InvalidAmount);
                 }
-                if let Some(new_total) = Amount::checked_add(self.total, amount) {
-                    self.ledger
-                        .insert(id, TransactionState::Deposit(amount))
-                        .await
-                        .and_then(|_| {
-                            self.total = new_total;
-                            Ok(()) //return success only if the ledger logged the transaction and everything was perfect!
-                        })
-                        .map_err(|_| TransactionError::DbError)
-                } else {
-                    Err(TransactionError::WouldOverFlow)
-                }
+                let new_total = self.total_after_deposit(amount)?;
+                let info = TransactionInfo {
+                    sequence: Some(self.transaction_count),
+                    ..info
+                };
+                self.ledger
+                    .insert(id, TransactionState::Deposit(amount, info))
+                    .await
+                    .map(|_| {
+                        //return success only if the ledger logged the transaction and everything was perfect!
+                        self.total = new_total;
+                        self.deposits.add(amount);
+                    })
+                    .map_err(|_| TransactionError::DbError)
             }
             Transaction::Withdrawal(amount) => {
-                if amount <= Amount::ZERO || self.available() < amount {
+                if amount <= Amount::ZERO {
+                    return Err(TransactionError::InvalidAmount);
+                }
+                if self.policy.pending_withdrawals
+                    && (!self.pending.is_empty() || self.available() < amount)
+                {
+                    //queue it behind the already pending ones, to keep the order of withdrawals
+                    return self
+                        .ledger
+                        .insert(id, TransactionState::PendingWithdrawal(amount, info))
+                        .await
+                        .map(|_| self.pending.push_back((id, amount, info)))
+                        .map_err(|_| TransactionError::DbError);
+                }
+                if self.available() < amount {
                     return Err(TransactionError::InvalidAmount); //* this case triggers the need for the ordered processing of transactions!
                 }
                 if let Some(new_total) = Amount::checked_sub(self.total, amount) {
                     self.ledger
-                        .insert(id, TransactionState::Withdrawal(amount))
+                        .insert(id, TransactionState::Withdrawal(amount, info))
                         .await
-                        .and_then(|_| {
+                        .map(|_| {
+                            //return success only if the ledger logged the transaction and everything was perfect!
                             self.total = new_total;
-                            Ok(()) //return success only if the ledger logged the transaction and everything was perfect!
                         })
                         .map_err(|_| TransactionError::DbError)
                 } else {
@@ -165,60 +444,93 @@ where
         }
     }
 
+    /// The state of the transaction an action refers to,
+    /// refused if it was created by another account sharing the ledger (only with the owner check)
+    async fn referred_transaction(
+        &self,
+        id: TransactionId,
+    ) -> Result<TransactionState, TransactionError> {
+        match self.ledger.get(id).await {
+            Err(_) => Err(TransactionError::DbError),
+            Ok(None) => Err(TransactionError::InvalidTransactionId),
+            //transactions of unknown owner (or an account without owner) are never owned
+            Ok(Some(state))
+                if self.policy.check_transaction_owner
+                    && (self.owner.is_none() || state.info().owner != self.owner) =>
+            {
+                Err(TransactionError::NotTransactionOwner)
+            }
+            Ok(Some(state)) => Ok(state),
+        }
+    }
+
     /// dispute represents a client's claim that a transaction was erroneous and
     /// should be reversed. The funds associated with this transaction should be
     /// held back from usage until the dispute resolution/charge back
     async fn start_dispute(&mut self, id: TransactionId) -> Result<(), TransactionError> {
-        match self.ledger.get(id).await {
-            Err(_) => Err(TransactionError::DbError),
-            Ok(None) => Err(TransactionError::InvalidTransactionId),
-            Ok(Some(state)) => match state {
-                TransactionState::ChargedBack(_) => Err(TransactionError::AlreadyChargedBack),
-                TransactionState::DepositInDispute(_) => Err(TransactionError::AlreadyInDispute),
-                TransactionState::Withdrawal(_) => Err(TransactionError::InvalidTransactionType),
-                TransactionState::Deposit(amount) => {
-                    if let Some(new_held) = Amount::checked_add(self.held, amount) {
-                        self.ledger
-                            .insert(id, TransactionState::DepositInDispute(amount))
-                            .await
-                            .and_then(|_| {
-                                self.held = new_held;
-                                Ok(())
-                            })
-                            .map_err(|_| TransactionError::DbError)
-                    } else {
-                        Err(TransactionError::WouldOverFlow)
+        match self.referred_transaction(id).await? {
+            TransactionState::ChargedBack(..) => Err(TransactionError::AlreadyChargedBack),
+            TransactionState::Reversed(..) => Err(TransactionError::AlreadyReversed),
+            TransactionState::DepositInDispute(..) => match self.policy.duplicate_dispute {
+                DuplicateDisputePolicy::Error => Err(TransactionError::AlreadyInDispute),
+                DuplicateDisputePolicy::Idempotent => Ok(()),
+            },
+            TransactionState::Withdrawal(..) | TransactionState::PendingWithdrawal(..) => {
+                Err(TransactionError::InvalidTransactionType)
+            }
+            TransactionState::Deposit(amount, mut info) => {
+                if let Some(window) = self.policy.dispute_window {
+                    //deposits of unknown age are considered too old
+                    match info.sequence {
+                        Some(sequence)
+                            if self.transaction_count.saturating_sub(sequence) <= window => {}
+                        _ => return Err(TransactionError::DisputeWindowExpired),
                     }
                 }
-            },
+                //the whole deposit gets held
+                if self.policy.forbid_negative_available && self.available() < amount {
+                    return Err(TransactionError::InsufficientFunds);
+                }
+                if let Some(new_held) = Amount::checked_add(self.held, amount) {
+                    info.held = amount;
+                    self.ledger
+                        .insert(id, TransactionState::DepositInDispute(amount, info))
+                        .await
+                        .map(|_| {
+                            self.held = new_held;
+                        })
+                        .map_err(|_| TransactionError::DbError)
+                } else {
+                    Err(TransactionError::WouldOverFlow)
+                }
+            }
         }
     }
 
     /// A resolve represents a resolution to a dispute, releasing the associated held funds
     async fn resolve_dispute(&mut self, id: TransactionId) -> Result<(), TransactionError> {
         //only open disputes can be resolved!
-        match self.ledger.get(id).await {
-            Err(_) => Err(TransactionError::DbError),
-            Ok(None) => Err(TransactionError::InvalidTransactionId),
-            Ok(Some(state)) => match state {
-                TransactionState::ChargedBack(_) => Err(TransactionError::AlreadyChargedBack),
-                TransactionState::Withdrawal(_) => Err(TransactionError::DisputeNotOpenedYet),
-                TransactionState::Deposit(_) => Err(TransactionError::DisputeNotOpenedYet),
-                TransactionState::DepositInDispute(amount) => {
-                    if let Some(new_held) = Amount::checked_sub(self.held, amount) {
-                        self.ledger
-                            .insert(id, TransactionState::Deposit(amount))
-                            .await
-                            .and_then(|_| {
-                                self.held = new_held;
-                                Ok(())
-                            })
-                            .map_err(|_| TransactionError::DbError)
-                    } else {
-                        Err(TransactionError::Unexpected)
-                    }
+        match self.referred_transaction(id).await? {
+            TransactionState::ChargedBack(..) => Err(TransactionError::AlreadyChargedBack),
+            TransactionState::Reversed(..) => Err(TransactionError::AlreadyReversed),
+            TransactionState::Withdrawal(..)
+            | TransactionState::PendingWithdrawal(..)
+            | TransactionState::Deposit(..) => Err(TransactionError::DisputeNotOpenedYet),
+            TransactionState::DepositInDispute(amount, mut info) => {
+                //releases exactly what the dispute held
+                if let Some(new_held) = Amount::checked_sub(self.held, info.held) {
+                    info.held = Amount::ZERO;
+                    self.ledger
+                        .insert(id, TransactionState::Deposit(amount, info))
+                        .await
+                        .map(|_| {
+                            self.held = new_held;
+                        })
+                        .map_err(|_| TransactionError::DbError)
+                } else {
+                    Err(TransactionError::Unexpected)
                 }
-            },
+            }
         }
     }
 
@@ -232,47 +544,267 @@ where
         id: TransactionId,
     ) -> Result<(), TransactionError> {
         //protect against repeated charge backs:
+        match self.referred_transaction(id).await? {
+            TransactionState::ChargedBack(..) => Err(TransactionError::AlreadyChargedBack),
+            TransactionState::Reversed(..) => Err(TransactionError::AlreadyReversed),
+            TransactionState::Withdrawal(..)
+            | TransactionState::PendingWithdrawal(..)
+            | TransactionState::Deposit(..) => Err(TransactionError::DisputeNotOpenedYet),
+            TransactionState::DepositInDispute(amount, mut info) => {
+                if let (Some(new_held), Some(new_total)) = (
+                    Amount::checked_sub(self.held, info.held),
+                    Amount::checked_sub(self.total, amount),
+                ) {
+                    info.held = Amount::ZERO;
+                    self.ledger
+                        .insert(id, TransactionState::ChargedBack(amount, info))
+                        .await
+                        .map(|_| {
+                            self.locked = true;
+                            self.total = new_total;
+                            self.held = new_held;
+                        })
+                        .map_err(|_| TransactionError::DbError)
+                } else {
+                    Err(TransactionError::Unexpected)
+                }
+            }
+        }
+    }
+
+    /// Reverses a deposit posted in error: its amount is removed from the total,
+    /// and the transaction is marked reversed (so it can not be disputed any more).
+    /// Refused if the funds of the deposit are not available any more (already withdrawn or held).
+    async fn reverse_deposit(&mut self, id: TransactionId) -> Result<(), TransactionError> {
+        if self.is_locked() {
+            return Err(TransactionError::AccountLocked);
+        }
+        match self.referred_transaction(id).await? {
+            TransactionState::ChargedBack(..) => Err(TransactionError::AlreadyChargedBack),
+            TransactionState::Reversed(..) => Err(TransactionError::AlreadyReversed),
+            TransactionState::DepositInDispute(..) => Err(TransactionError::AlreadyInDispute),
+            TransactionState::Withdrawal(..) | TransactionState::PendingWithdrawal(..) => {
+                Err(TransactionError::InvalidTransactionType)
+            }
+            TransactionState::Deposit(amount, info) => {
+                if self.available() < amount {
+                    return Err(TransactionError::InsufficientFunds);
+                }
+                if let Some(new_total) = Amount::checked_sub(self.total, amount) {
+                    self.ledger
+                        .insert(id, TransactionState::Reversed(amount, info))
+                        .await
+                        .map(|_| {
+                            self.total = new_total;
+                        })
+                        .map_err(|_| TransactionError::DbError)
+                } else {
+                    Err(TransactionError::Unexpected)
+                }
+            }
+        }
+    }
+
+    /// Executes the pending withdrawals (in their original order) as long as the available funds cover them.
+    /// A failing ledger leaves the rest of the withdrawals pending, they will be retried on the next occasion.
+    async fn settle_pending_withdrawals(&mut self) {
+        while let Some(&(id, amount, info)) = self.pending.front() {
+            if self.is_locked() || self.available() < amount {
+                break;
+            }
+            if let Some(new_total) = Amount::checked_sub(self.total, amount) {
+                if self
+                    .ledger
+                    .insert(id, TransactionState::Withdrawal(amount, info))
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+                self.total = new_total;
+                self.pending.pop_front();
+            } else {
+                break;
+            }
+        }
+    }
+
+    /// Checks whether a transfer of the given amount could be credited to this account as 'id'
+    /// (the first step of a transfer coordinated by AccountHub, nothing is changed)
+    pub async fn check_transfer_in(
+        &self,
+        id: TransactionId,
+        amount: Amount,
+    ) -> Result<(), TransactionError> {
+        self.check_open()?;
+        if self.is_locked() {
+            return Err(TransactionError::AccountLocked);
+        }
+        match self.ledger.contains(id).await {
+            Ok(true) => return Err(TransactionError::RepeatedTransactionId),
+            Err(_) => return Err(TransactionError::DbError),
+            _ => {}
+        }
+        if amount <= Amount::ZERO {
+            return Err(TransactionError::InvalidAmount);
+        }
+        self.total_after_deposit(amount).map(|_| ())
+    }
+
+    /// The total funds after depositing the amount, unless it would overflow or exceed the cap
+    fn total_after_deposit(&self, amount: Amount) -> Result<Amount, TransactionError> {
+        let new_total =
+            Amount::checked_add(self.total, amount).ok_or(TransactionError::WouldOverFlow)?;
+        match self.policy.max_balance {
+            Some(max_balance) if new_total > max_balance => {
+                Err(TransactionError::ExceedsBalanceCap)
+            }
+            _ => Ok(new_total),
+        }
+    }
+
+    /// The debit side of a transfer: a withdrawal which is never queued as pending,
+    /// refused if the available funds are not enough right now
+    pub async fn transfer_out(
+        &mut self,
+        id: TransactionId,
+        amount: Amount,
+    ) -> Result<(), TransactionError> {
+        if !self.pending.is_empty() || self.available() < amount {
+            return Err(TransactionError::InsufficientFunds);
+        }
+        self.execute(Action::Transact(
+            (id, Transaction::Withdrawal(amount)),
+            None,
+        ))
+        .await
+    }
+
+    /// Undoes the debit side of a transfer whose credit failed: the funds are returned
+    /// and the withdrawal is marked as reversed in the ledger
+    pub async fn refund_transfer_out(
+        &mut self,
+        id: TransactionId,
+        amount: Amount,
+    ) -> Result<(), TransactionError> {
+        self.check_open()?;
         match self.ledger.get(id).await {
+            Ok(Some(TransactionState::Withdrawal(withdrawn, info))) if withdrawn == amount => {
+                let new_total = Amount::checked_add(self.total, amount)
+                    .ok_or(TransactionError::WouldOverFlow)?;
+                self.ledger
+                    .insert(id, TransactionState::Reversed(amount, info))
+                    .await
+                    .map_err(|_| TransactionError::DbError)?;
+                self.total = new_total;
+                Ok(())
+            }
+            Ok(_) => Err(TransactionError::InvalidTransactionId),
             Err(_) => Err(TransactionError::DbError),
-            Ok(None) => Err(TransactionError::InvalidTransactionId),
-            Ok(Some(state)) => match state {
-                TransactionState::ChargedBack(_) => Err(TransactionError::AlreadyChargedBack),
-                TransactionState::Withdrawal(_) => Err(TransactionError::DisputeNotOpenedYet),
-                TransactionState::Deposit(_) => Err(TransactionError::DisputeNotOpenedYet),
-                TransactionState::DepositInDispute(amount) => {
-                    if let (Some(new_held), Some(new_total)) = (
-                        Amount::checked_sub(self.held, amount),
-                        Amount::checked_sub(self.total, amount),
-                    ) {
-                        self.ledger
-                            .insert(id, TransactionState::ChargedBack(amount))
-                            .await
-                            .and_then(|_| {
-                                self.locked = true;
-                                self.total = new_total;
-                                self.held = new_held;
-                                Ok(())
-                            })
-                            .map_err(|_| TransactionError::DbError)
-                    } else {
-                        Err(TransactionError::Unexpected)
-                    }
-                }
-            },
         }
     }
 
-    /// The one and only entry point to mutate the state of an Account.
+    /// The credit side of a transfer: a deposit
+    pub async fn transfer_in(
+        &mut self,
+        id: TransactionId,
+        amount: Amount,
+    ) -> Result<(), TransactionError> {
+        self.execute(Action::Transact((id, Transaction::Deposit(amount)), None))
+            .await
+    }
+
+    /// Clears the ledger and returns to the starting state (keeping the policy).
+    /// With the owner check (a shared ledger) only the transactions of this account are removed.
+    /// If the ledger does not support removal (or the owner of a shared ledger is unknown)
+    /// it is refused with 'ResetUnsupported', without the "dangerous-admin" feature it is ignored,
+    /// both leave the account untouched.
+    #[cfg(feature = "dangerous-admin")]
+    async fn reset(&mut self) -> Result<(), TransactionError> {
+        let cleared = match (self.policy.check_transaction_owner, self.owner) {
+            (false, _) => self.ledger.clear().await,
+            (true, Some(owner)) => self.ledger.clear_owned(owner).await,
+            (true, None) => Ok(false),
+        };
+        match cleared {
+            Ok(true) => {}
+            Ok(false) => return Err(TransactionError::ResetUnsupported),
+            Err(_) => return Err(TransactionError::DbError),
+        }
+        self.total = Amount::ZERO;
+        self.held = Amount::ZERO;
+        self.locked = false;
+        self.transaction_count = 0;
+        self.pending.clear();
+        self.deposits = empty_deposit_stats(&self.policy);
+        Ok(())
+    }
+
+    #[cfg(not(feature = "dangerous-admin"))]
+    async fn reset(&mut self) -> Result<(), TransactionError> {
+        Ok(())
+    }
+
+    /// Closes the ledger (only once), every later action or ledger query is refused with 'LedgerClosed'
+    /// (the balances remain readable)
+    pub async fn close(&mut self) -> Result<(), TransactionError> {
+        if self.closed {
+            return Ok(());
+        }
+        self.closed = true;
+        self.ledger
+            .close()
+            .await
+            .map_err(|_| TransactionError::DbError)
+    }
+
+    /// The one and only entry point to mutate the state of an Account
+    /// (the transfer steps above are executing through it too).
     /// The execution order of the transactions must be kept.
     /// (In other words: out of order transaction processing must NOT be used!)
     /// Concurrent transaction processing is also forbidden!
+    /// An action is recorded in the ledger only after all of its checks passed,
+    /// so a refused action never leaves a partial state behind.
     pub async fn execute(&mut self, action: Action) -> Result<(), TransactionError> {
-        match action {
-            Action::Transact((id, transaction)) => self.transact(id, transaction).await,
+        self.check_open()?;
+        let result = match action {
+            Action::Transact((id, transaction), reference) => {
+                self.transact(id, transaction, reference.as_deref()).await
+            }
             Action::Dispute(id) => self.start_dispute(id).await,
             Action::Resolve(id) => self.resolve_dispute(id).await,
             Action::ChargeBack(id) => self.resolve_dispute_with_charge_back(id).await,
+            Action::ReverseDeposit(id) => self.reverse_deposit(id).await,
+            //a single account can not execute it, see 'transfer_out' and 'transfer_in'
+            Action::Transfer { .. } => Err(TransactionError::InvalidTransactionType),
+            //not counted as an executed action, the count restarts too
+            Action::Reset => return self.reset().await,
+            Action::ConditionalWithdraw {
+                id,
+                amount,
+                expected_available,
+            } => {
+                if self.available() != expected_available {
+                    Err(TransactionError::PreconditionFailed)
+                } else if self.policy.pending_withdrawals
+                    && (!self.pending.is_empty() || self.available() < amount)
+                {
+                    //never queued as pending (like 'transfer_out'), the precondition holds only now
+                    Err(TransactionError::InsufficientFunds)
+                } else {
+                    self.transact(id, Transaction::Withdrawal(amount), None)
+                        .await
+                }
+            }
+        };
+        if result.is_ok() {
+            self.transaction_count += 1;
+            if !self.pending.is_empty() {
+                //deposits and resolved disputes may have freed funds for the pending withdrawals
+                self.settle_pending_withdrawals().await;
+            }
         }
+        result
     }
 }
 
@@ -290,10 +822,13 @@ mod tests {
     ) {
         assert_eq!(
             account
-                .execute(Action::Transact((
-                    TransactionId::from(id),
-                    Transaction::Deposit(Amount::from_str(amount).unwrap())
-                )))
+                .execute(Action::Transact(
+                    (
+                        TransactionId::from(id),
+                        Transaction::Deposit(Amount::from_str(amount).unwrap())
+                    ),
+                    None
+                ))
                 .await,
             expected
         );
@@ -307,10 +842,13 @@ mod tests {
     ) {
         assert_eq!(
             account
-                .execute(Action::Transact((
-                    TransactionId::from(id),
-                    Transaction::Withdrawal(Amount::from_str(amount).unwrap())
-                )))
+                .execute(Action::Transact(
+                    (
+                        TransactionId::from(id),
+                        Transaction::Withdrawal(Amount::from_str(amount).unwrap())
+                    ),
+                    None
+                ))
                 .await,
             expected
         );
@@ -353,6 +891,38 @@ mod tests {
         );
     }
 
+    async fn reverse_deposit(
+        account: &mut Account<InMemoryLedger>,
+        id: u32,
+        expected: Result<(), TransactionError>,
+    ) {
+        assert_eq!(
+            account
+                .execute(Action::ReverseDeposit(TransactionId::from(id)))
+                .await,
+            expected
+        );
+    }
+
+    async fn conditional_withdraw(
+        account: &mut Account<InMemoryLedger>,
+        id: u32,
+        amount: &str,
+        expected_available: &str,
+        expected: Result<(), TransactionError>,
+    ) {
+        assert_eq!(
+            account
+                .execute(Action::ConditionalWithdraw {
+                    id: TransactionId::from(id),
+                    amount: Amount::from_str(amount).unwrap(),
+                    expected_available: Amount::from_str(expected_available).unwrap(),
+                })
+                .await,
+            expected
+        );
+    }
+
     fn expect_balance(
         account: &mut Account<InMemoryLedger>,
         available: &str,
@@ -370,12 +940,84 @@ mod tests {
         Account::new(InMemoryLedger::connect().unwrap())
     }
 
+    fn connect_with_pending_withdrawals() -> Account<InMemoryLedger> {
+        Account::with_policy(
+            InMemoryLedger::connect().unwrap(),
+            AccountPolicy {
+                pending_withdrawals: true,
+                ..AccountPolicy::default()
+            },
+        )
+    }
+
+    /// compares the name and the amount of the state (see its Display), not the bookkeeping
+    async fn expect_state(account: &Account<InMemoryLedger>, id: u32, state: &str) {
+        assert_eq!(
+            account
+                .ledger
+                .get(TransactionId::from(id))
+                .await
+                .map(|state| state.map(|state| state.to_string())),
+            Ok(Some(state.to_string()))
+        );
+    }
+
     #[tokio::test]
     async fn starting_from_zero() {
         let account = connect();
         assert_eq!(account.available(), Amount::ZERO);
         assert_eq!(account.total(), Amount::ZERO);
         assert_eq!(account.held(), Amount::ZERO);
+        assert_eq!(account.transaction_count(), 0);
+    }
+
+    #[cfg(feature = "dangerous-admin")]
+    #[tokio::test]
+    async fn reset() {
+        let mut account = connect_with_pending_withdrawals();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        deposit(&mut account, 2, "5", Ok(())).await;
+        withdraw(&mut account, 3, "20", Ok(())).await;
+        dispute(&mut account, 2, Ok(())).await;
+        charge_back(&mut account, 2, Ok(())).await;
+        assert!(account.is_locked());
+
+        assert_eq!(account.execute(Action::Reset).await, Ok(()));
+        assert_eq!(account.available(), Amount::ZERO);
+        assert_eq!(account.total(), Amount::ZERO);
+        assert_eq!(account.held(), Amount::ZERO);
+        assert_eq!(account.pending(), Amount::ZERO);
+        assert!(!account.is_locked());
+        assert_eq!(account.transaction_count(), 0);
+        assert_eq!(account.ledger.get(TransactionId::from(1)).await, Ok(None));
+
+        //the transaction ids can be used again
+        deposit(&mut account, 1, "3", Ok(())).await;
+        expect_balance(&mut account, "3", "3", "0", false);
+    }
+
+    #[cfg(not(feature = "dangerous-admin"))]
+    #[tokio::test]
+    async fn reset_ignored() {
+        let mut account = connect();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        assert_eq!(account.execute(Action::Reset).await, Ok(()));
+        expect_balance(&mut account, "10", "10", "0", false);
+        assert_eq!(account.transaction_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn counting_transactions() {
+        let mut account = connect();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        deposit(&mut account, 2, "0", Err(TransactionError::InvalidAmount)).await;
+        withdraw(&mut account, 3, "4", Ok(())).await;
+        dispute(&mut account, 1, Ok(())).await;
+        dispute(&mut account, 1, Err(TransactionError::AlreadyInDispute)).await;
+        resolve(&mut account, 1, Ok(())).await;
+        dispute(&mut account, 1, Ok(())).await;
+        charge_back(&mut account, 1, Ok(())).await;
+        assert_eq!(account.transaction_count(), 6);
     }
 
     #[tokio::test]
@@ -527,4 +1169,829 @@ mod tests {
         .await; //TODO ASK! - Is it possible to dispute a withdrawal?
         expect_balance(&mut account, "6", "6", "0", false);
     }
+
+    #[tokio::test]
+    async fn pending_withdrawal_fulfilled_by_deposit() {
+        let mut account = connect_with_pending_withdrawals();
+        let amount = |s| Amount::from_str(s).unwrap();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        withdraw(&mut account, 2, "0", Err(TransactionError::InvalidAmount)).await;
+        withdraw(&mut account, 3, "15", Ok(())).await;
+        withdraw(&mut account, 4, "1", Ok(())).await; //queued behind the previous one
+        expect_balance(&mut account, "10", "10", "0", false);
+        assert_eq!(account.pending(), amount("16"));
+        expect_state(&account, 3, "PendingWithdrawal(15)").await;
+
+        deposit(&mut account, 5, "5", Ok(())).await;
+        expect_balance(&mut account, "0", "0", "0", false);
+        assert_eq!(account.pending(), amount("1"));
+        expect_state(&account, 3, "Withdrawal(15)").await;
+        expect_state(&account, 4, "PendingWithdrawal(1)").await;
+        dispute(
+            &mut account,
+            4,
+            Err(TransactionError::InvalidTransactionType),
+        )
+        .await;
+
+        deposit(&mut account, 6, "2", Ok(())).await;
+        expect_balance(&mut account, "1", "1", "0", false);
+        assert_eq!(account.pending(), Amount::ZERO);
+        expect_state(&account, 4, "Withdrawal(1)").await;
+    }
+
+    #[tokio::test]
+    async fn pending_withdrawal_never_funded() {
+        let mut account = connect_with_pending_withdrawals();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        withdraw(&mut account, 2, "100", Ok(())).await;
+        deposit(&mut account, 3, "20", Ok(())).await;
+        withdraw(&mut account, 4, "5", Ok(())).await; //queued behind the previous one
+        expect_balance(&mut account, "30", "30", "0", false);
+        assert_eq!(account.pending(), Amount::from_str("105").unwrap());
+
+        //the default policy still refuses it
+        let mut account = connect();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        withdraw(&mut account, 2, "100", Err(TransactionError::InvalidAmount)).await;
+        expect_balance(&mut account, "10", "10", "0", false);
+        assert_eq!(account.pending(), Amount::ZERO);
+    }
+
+    #[tokio::test]
+    async fn conditional_withdrawals() {
+        let mut account = connect();
+        deposit(&mut account, 1, "100", Ok(())).await;
+        conditional_withdraw(&mut account, 2, "30", "100", Ok(())).await;
+        expect_balance(&mut account, "70", "70", "0", false);
+
+        conditional_withdraw(
+            &mut account,
+            3,
+            "30",
+            "100",
+            Err(TransactionError::PreconditionFailed),
+        )
+        .await;
+        expect_balance(&mut account, "70", "70", "0", false);
+        assert_eq!(
+            account.ledger.contains(TransactionId::from(3)).await,
+            Ok(false)
+        );
+
+        //the usual withdrawal rules still apply when the precondition holds
+        conditional_withdraw(
+            &mut account,
+            4,
+            "71",
+            "70",
+            Err(TransactionError::InvalidAmount),
+        )
+        .await;
+        expect_balance(&mut account, "70", "70", "0", false);
+    }
+
+    #[tokio::test]
+    async fn conditional_withdrawals_never_pending() {
+        let mut account = connect_with_pending_withdrawals();
+        deposit(&mut account, 1, "100", Ok(())).await;
+        conditional_withdraw(
+            &mut account,
+            2,
+            "101",
+            "100",
+            Err(TransactionError::InsufficientFunds),
+        )
+        .await;
+        expect_balance(&mut account, "100", "100", "0", false);
+        assert_eq!(account.pending(), Amount::ZERO);
+        assert_eq!(
+            account.ledger.contains(TransactionId::from(2)).await,
+            Ok(false)
+        );
+
+        //it does not jump ahead of the already pending withdrawals either
+        withdraw(&mut account, 3, "150", Ok(())).await;
+        conditional_withdraw(
+            &mut account,
+            4,
+            "10",
+            "100",
+            Err(TransactionError::InsufficientFunds),
+        )
+        .await;
+        expect_balance(&mut account, "100", "100", "0", false);
+        assert_eq!(account.pending(), Amount::from_str("150").unwrap());
+
+        deposit(&mut account, 5, "50", Ok(())).await;
+        expect_balance(&mut account, "0", "0", "0", false);
+        conditional_withdraw(
+            &mut account,
+            6,
+            "1",
+            "1",
+            Err(TransactionError::PreconditionFailed),
+        )
+        .await;
+        deposit(&mut account, 7, "1", Ok(())).await;
+        conditional_withdraw(&mut account, 8, "1", "1", Ok(())).await;
+        expect_balance(&mut account, "0", "0", "0", false);
+    }
+
+    #[tokio::test]
+    async fn duplicate_disputes() {
+        let mut account = connect();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        dispute(&mut account, 1, Ok(())).await;
+        dispute(&mut account, 1, Err(TransactionError::AlreadyInDispute)).await;
+        expect_balance(&mut account, "0", "10", "10", false);
+
+        let mut account = Account::with_policy(
+            InMemoryLedger::connect().unwrap(),
+            AccountPolicy {
+                duplicate_dispute: DuplicateDisputePolicy::Idempotent,
+                ..AccountPolicy::default()
+            },
+        );
+        deposit(&mut account, 1, "10", Ok(())).await;
+        dispute(&mut account, 1, Ok(())).await;
+        dispute(&mut account, 1, Ok(())).await; //no double hold
+        expect_balance(&mut account, "0", "10", "10", false);
+        resolve(&mut account, 1, Ok(())).await;
+        expect_balance(&mut account, "10", "10", "0", false);
+        resolve(&mut account, 1, Err(TransactionError::DisputeNotOpenedYet)).await;
+    }
+
+    #[tokio::test]
+    async fn refused_overflows_leave_ledger_untouched() {
+        let max = "922337203685477.5807";
+        let mut account = connect();
+        deposit(&mut account, 1, max, Ok(())).await;
+        deposit(&mut account, 2, "1", Err(TransactionError::WouldOverFlow)).await;
+        assert_eq!(
+            account.ledger.contains(TransactionId::from(2)).await,
+            Ok(false)
+        );
+        withdraw(&mut account, 3, max, Ok(())).await;
+        deposit(&mut account, 4, max, Ok(())).await;
+        withdraw(&mut account, 5, max, Ok(())).await;
+
+        //held would overflow
+        dispute(&mut account, 1, Ok(())).await;
+        dispute(&mut account, 4, Err(TransactionError::WouldOverFlow)).await;
+        expect_state(&account, 4, "Deposit(922337203685477.5807)").await;
+        assert_eq!(account.held(), Amount::MAX);
+
+        //total would overflow
+        charge_back(&mut account, 1, Ok(())).await;
+        expect_balance(
+            &mut account,
+            "-922337203685477.5807",
+            "-922337203685477.5807",
+            "0",
+            true,
+        );
+        dispute(&mut account, 4, Ok(())).await;
+        charge_back(&mut account, 4, Err(TransactionError::Unexpected)).await;
+        expect_state(&account, 4, "DepositInDispute(922337203685477.5807)").await;
+        assert_eq!(
+            account.total(),
+            Amount::from_str("-922337203685477.5807").unwrap()
+        );
+        assert_eq!(account.held(), Amount::MAX);
+    }
+
+    #[tokio::test]
+    async fn disputes_with_negative_available_forbidden() {
+        for forbid_negative_available in [false, true] {
+            let mut account = Account::with_policy(
+                InMemoryLedger::connect().unwrap(),
+                AccountPolicy {
+                    forbid_negative_available,
+                    ..AccountPolicy::default()
+                },
+            );
+            deposit(&mut account, 3, "100", Ok(())).await;
+            withdraw(&mut account, 5, "5", Ok(())).await;
+            deposit(&mut account, 7, "200", Ok(())).await;
+            withdraw(&mut account, 8, "290", Ok(())).await;
+            deposit(&mut account, 9, "1", Ok(())).await;
+            expect_balance(&mut account, "6", "6", "0", false);
+            dispute(&mut account, 9, Ok(())).await;
+            expect_balance(&mut account, "5", "6", "1", false);
+
+            if forbid_negative_available {
+                dispute(&mut account, 7, Err(TransactionError::InsufficientFunds)).await;
+                expect_balance(&mut account, "5", "6", "1", false);
+                expect_state(&account, 7, "Deposit(200)").await;
+                dispute(&mut account, 3, Err(TransactionError::InsufficientFunds)).await;
+                withdraw(&mut account, 10, "4", Ok(())).await;
+                dispute(&mut account, 9, Err(TransactionError::AlreadyInDispute)).await;
+                expect_balance(&mut account, "1", "2", "1", false);
+            } else {
+                dispute(&mut account, 7, Ok(())).await;
+                expect_balance(&mut account, "-195", "6", "201", false);
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn reversed_deposits() {
+        let mut account = connect();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        deposit(&mut account, 2, "10", Ok(())).await;
+        reverse_deposit(&mut account, 2, Ok(())).await;
+        expect_balance(&mut account, "10", "10", "0", false);
+        expect_state(&account, 2, "Reversed(10)").await;
+
+        reverse_deposit(&mut account, 2, Err(TransactionError::AlreadyReversed)).await;
+        dispute(&mut account, 2, Err(TransactionError::AlreadyReversed)).await;
+        resolve(&mut account, 2, Err(TransactionError::AlreadyReversed)).await;
+        reverse_deposit(&mut account, 3, Err(TransactionError::InvalidTransactionId)).await;
+        withdraw(&mut account, 4, "1", Ok(())).await;
+        reverse_deposit(
+            &mut account,
+            4,
+            Err(TransactionError::InvalidTransactionType),
+        )
+        .await;
+        expect_balance(&mut account, "9", "9", "0", false);
+    }
+
+    #[tokio::test]
+    async fn references() {
+        let with_reference = |id, transaction, reference: &str| {
+            Action::Transact((id, transaction), Some(reference.to_string()))
+        };
+        let mut account = connect();
+        let id = TransactionId::from(1);
+        let reference = Reference::new("INV-001").unwrap();
+        let deposit = Transaction::Deposit(Amount::from_str("10").unwrap());
+        assert_eq!(
+            account
+                .execute(with_reference(id, deposit, "INV-001"))
+                .await,
+            Ok(())
+        );
+        expect_balance(&mut account, "10", "10", "0", false);
+        expect_state(&account, 1, "Deposit(10)").await;
+        assert_eq!(account.reference(id).await, Ok(Some(reference)));
+
+        //a repeated id neither changes the balance nor overwrites the original reference
+        assert_eq!(
+            account
+                .execute(with_reference(id, deposit, "INV-002"))
+                .await,
+            Err(TransactionError::RepeatedTransactionId)
+        );
+        assert_eq!(account.reference(id).await, Ok(Some(reference)));
+
+        //a refused transaction leaves nothing in the ledger
+        let id = TransactionId::from(2);
+        let withdrawal = Transaction::Withdrawal(Amount::from_str("11").unwrap());
+        assert_eq!(
+            account
+                .execute(with_reference(id, withdrawal, "INV-001"))
+                .await,
+            Err(TransactionError::InvalidAmount)
+        );
+        assert_eq!(account.transaction_state(id).await, Ok(None));
+        assert_eq!(account.reference(id).await, Ok(None));
+        //so does an invalid reference
+        for invalid in ["".to_string(), "x".repeat(Reference::MAX_LEN + 1)] {
+            assert_eq!(
+                account.execute(with_reference(id, deposit, &invalid)).await,
+                Err(TransactionError::InvalidReference)
+            );
+        }
+        assert_eq!(account.transaction_state(id).await, Ok(None));
+
+        //the reference survives the dispute workflow
+        dispute(&mut account, 1, Ok(())).await;
+        resolve(&mut account, 1, Ok(())).await;
+        assert_eq!(
+            account.reference(TransactionId::from(1)).await,
+            Ok(Some(reference))
+        );
+        expect_balance(&mut account, "10", "10", "0", false);
+        assert_eq!(account.transaction_count(), 3);
+
+        //a pending withdrawal keeps its reference when it gets executed
+        let mut account = connect_with_pending_withdrawals();
+        assert_eq!(
+            account
+                .execute(with_reference(id, withdrawal, "INV-001"))
+                .await,
+            Ok(())
+        );
+        expect_state(&account, 2, "PendingWithdrawal(11)").await;
+        let funding = Transaction::Deposit(Amount::from_str("11").unwrap());
+        assert_eq!(
+            account
+                .execute(Action::Transact((TransactionId::from(3), funding), None))
+                .await,
+            Ok(())
+        );
+        expect_state(&account, 2, "Withdrawal(11)").await;
+        assert_eq!(account.reference(id).await, Ok(Some(reference)));
+    }
+
+    #[tokio::test]
+    async fn closed_ledger() {
+        let mut account = connect();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        assert_eq!(account.close().await, Ok(()));
+        //closing again does not touch the ledger
+        assert_eq!(account.close().await, Ok(()));
+        deposit(&mut account, 2, "5", Err(TransactionError::LedgerClosed)).await;
+        dispute(&mut account, 1, Err(TransactionError::LedgerClosed)).await;
+        assert_eq!(
+            account.transaction_state(TransactionId::from(1)).await,
+            Err(TransactionError::LedgerClosed)
+        );
+        assert_eq!(
+            account
+                .check_transfer_in(TransactionId::from(3), Amount::ONE)
+                .await,
+            Err(TransactionError::LedgerClosed)
+        );
+        //the balances are kept for the summary
+        expect_balance(&mut account, "10", "10", "0", false);
+        assert_eq!(account.transaction_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn reversal_blocked_by_spent_funds() {
+        let mut account = connect();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        deposit(&mut account, 2, "5", Ok(())).await;
+        withdraw(&mut account, 3, "12", Ok(())).await;
+        reverse_deposit(&mut account, 1, Err(TransactionError::InsufficientFunds)).await;
+        expect_balance(&mut account, "3", "3", "0", false);
+        expect_state(&account, 1, "Deposit(10)").await;
+
+        //held funds can not be reversed either
+        dispute(&mut account, 2, Ok(())).await;
+        reverse_deposit(&mut account, 2, Err(TransactionError::AlreadyInDispute)).await;
+        expect_balance(&mut account, "-2", "3", "5", false);
+    }
+
+    #[test]
+    fn retryable_errors() {
+        for (err, retryable) in [
+            (TransactionError::AccountLocked, false),
+            (TransactionError::InvalidAmount, false),
+            (TransactionError::WouldOverFlow, false),
+            (TransactionError::ExceedsBalanceCap, false),
+            (TransactionError::InsufficientFunds, false),
+            (TransactionError::DisputeNotOpenedYet, false),
+            (TransactionError::AlreadyInDispute, false),
+            (TransactionError::AlreadyChargedBack, false),
+            (TransactionError::AlreadyReversed, false),
+            (TransactionError::DisputeWindowExpired, false),
+            (TransactionError::SelfTransfer, false),
+            (TransactionError::TransferSourceMismatch, false),
+            (TransactionError::ResetUnsupported, false),
+            (TransactionError::InvalidReference, false),
+            (TransactionError::LedgerClosed, false),
+            (TransactionError::TransferPartiallyFailed, false),
+            (TransactionError::InvalidTransactionId, false),
+            (TransactionError::NotTransactionOwner, false),
+            (TransactionError::InvalidTransactionType, false),
+            (TransactionError::RepeatedTransactionId, false),
+            (TransactionError::PreconditionFailed, false),
+            (TransactionError::DbError, true),
+            (TransactionError::Unexpected, false),
+        ] {
+            assert_eq!(err.retryable(), retryable, "{err}");
+        }
+    }
+
+    #[tokio::test]
+    async fn dispute_resolve_cycles() {
+        let mut account = connect();
+        deposit(&mut account, 3, "100", Ok(())).await;
+        withdraw(&mut account, 5, "95", Ok(())).await;
+        deposit(&mut account, 7, "200", Ok(())).await;
+        withdraw(&mut account, 8, "200", Ok(())).await;
+        for _ in 0..3 {
+            dispute(&mut account, 7, Ok(())).await;
+            dispute(&mut account, 7, Err(TransactionError::AlreadyInDispute)).await;
+            expect_balance(&mut account, "-195", "5", "200", false);
+            resolve(&mut account, 7, Ok(())).await;
+            expect_balance(&mut account, "5", "5", "0", false);
+        }
+        //holding two deposits at once holds exactly their sum
+        dispute(&mut account, 3, Ok(())).await;
+        dispute(&mut account, 7, Ok(())).await;
+        expect_balance(&mut account, "-295", "5", "300", false);
+    }
+
+    #[tokio::test]
+    async fn held_tracked_per_deposit() {
+        async fn held(account: &Account<InMemoryLedger>, id: u32) -> Amount {
+            let state = account.transaction_state(TransactionId::from(id)).await;
+            state.unwrap().unwrap().info().held
+        }
+        let mut account = connect();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        deposit(&mut account, 2, "4", Ok(())).await;
+        assert_eq!(held(&account, 1).await, Amount::ZERO);
+        for _ in 0..2 {
+            dispute(&mut account, 1, Ok(())).await;
+            assert_eq!(held(&account, 1).await, Amount::from(10));
+            assert_eq!(held(&account, 2).await, Amount::ZERO);
+            resolve(&mut account, 1, Ok(())).await;
+            assert_eq!(held(&account, 1).await, Amount::ZERO);
+            expect_balance(&mut account, "14", "14", "0", false);
+        }
+        dispute(&mut account, 1, Ok(())).await;
+        dispute(&mut account, 2, Ok(())).await;
+        assert_eq!(held(&account, 2).await, Amount::from(4));
+        expect_balance(&mut account, "0", "14", "14", false);
+        charge_back(&mut account, 2, Ok(())).await;
+        assert_eq!(held(&account, 2).await, Amount::ZERO);
+        expect_balance(&mut account, "0", "10", "10", true);
+        //the charge back of one deposit leaves the funds held for the other one intact
+        assert_eq!(held(&account, 1).await, Amount::from(10));
+    }
+
+    #[test]
+    fn action_weights() {
+        let id = TransactionId::from(1);
+        let transacts = [
+            Action::Transact((id, Transaction::Deposit(Amount::ONE)), None),
+            Action::Transact((id, Transaction::Withdrawal(Amount::ONE)), None),
+            Action::ConditionalWithdraw {
+                id,
+                amount: Amount::ONE,
+                expected_available: Amount::ONE,
+            },
+        ];
+        let state_changes = [
+            Action::Dispute(id),
+            Action::Resolve(id),
+            Action::ChargeBack(id),
+            Action::ReverseDeposit(id),
+        ];
+        for transact in transacts {
+            assert_eq!(transact.weight(), 2);
+            for state_change in &state_changes {
+                assert_eq!(state_change.weight(), 1);
+                assert!(transact.weight() > state_change.weight());
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn balance_cap() {
+        let mut account = Account::with_policy(
+            InMemoryLedger::connect().unwrap(),
+            AccountPolicy {
+                max_balance: Amount::from_str("100").ok(),
+                ..AccountPolicy::default()
+            },
+        );
+        deposit(&mut account, 1, "60", Ok(())).await;
+        deposit(&mut account, 2, "40", Ok(())).await; //exactly the cap
+        expect_balance(&mut account, "100", "100", "0", false);
+        deposit(
+            &mut account,
+            3,
+            "0.0001",
+            Err(TransactionError::ExceedsBalanceCap),
+        )
+        .await;
+        expect_balance(&mut account, "100", "100", "0", false);
+        assert_eq!(account.ledger.get(TransactionId::from(3)).await, Ok(None));
+        assert_eq!(
+            account
+                .check_transfer_in(TransactionId::from(4), Amount::ONE)
+                .await,
+            Err(TransactionError::ExceedsBalanceCap)
+        );
+
+        withdraw(&mut account, 5, "30", Ok(())).await;
+        deposit(
+            &mut account,
+            6,
+            "30.0001",
+            Err(TransactionError::ExceedsBalanceCap),
+        )
+        .await;
+        deposit(&mut account, 7, "30", Ok(())).await;
+        expect_balance(&mut account, "100", "100", "0", false);
+        //the overflow is still reported as such
+        account.policy.max_balance = Some(Amount::MAX);
+        deposit(
+            &mut account,
+            8,
+            "922337203685477",
+            Err(TransactionError::WouldOverFlow),
+        )
+        .await;
+    }
+
+    #[tokio::test]
+    async fn dispute_window() {
+        let mut account = Account::with_policy(
+            InMemoryLedger::connect().unwrap(),
+            AccountPolicy {
+                dispute_window: Some(3),
+                ..AccountPolicy::default()
+            },
+        );
+        deposit(&mut account, 1, "10", Ok(())).await; //sequence 0
+        deposit(&mut account, 2, "10", Ok(())).await; //sequence 1
+        deposit(&mut account, 3, "10", Ok(())).await; //sequence 2
+        withdraw(&mut account, 4, "100", Err(TransactionError::InvalidAmount)).await; //not counted
+
+        //3 actions since deposit 1: still within the window
+        dispute(&mut account, 1, Ok(())).await;
+        resolve(&mut account, 1, Ok(())).await;
+        //5 actions since deposit 1, 4 since deposit 2, 3 since deposit 3
+        dispute(&mut account, 1, Err(TransactionError::DisputeWindowExpired)).await;
+        dispute(&mut account, 2, Err(TransactionError::DisputeWindowExpired)).await;
+        dispute(&mut account, 3, Ok(())).await;
+        expect_balance(&mut account, "20", "30", "10", false);
+
+        //the sequence is stored with the deposit, deposits of unknown age are considered too old
+        let state = account.transaction_state(TransactionId::from(2)).await;
+        assert_eq!(state.unwrap().unwrap().info().sequence, Some(1));
+        account
+            .ledger
+            .insert(
+                TransactionId::from(5),
+                TransactionState::Deposit(Amount::ONE, TransactionInfo::default()),
+            )
+            .await
+            .unwrap();
+        dispute(&mut account, 5, Err(TransactionError::DisputeWindowExpired)).await;
+
+        //without the window any deposit can be disputed
+        let mut account = connect();
+        deposit(&mut account, 1, "10", Ok(())).await;
+        for id in 2..10 {
+            deposit(&mut account, id, "1", Ok(())).await;
+        }
+        dispute(&mut account, 1, Ok(())).await;
+    }
+
+    /// A ledger shared by several accounts (like a global transaction registry)
+    #[derive(Clone)]
+    struct SharedLedger(std::sync::Arc<tokio::sync::Mutex<InMemoryLedger>>);
+
+    #[async_trait::async_trait]
+    impl Ledger for SharedLedger {
+        type Error = LedgerError;
+
+        async fn contains(&self, key: TransactionId) -> Result<bool, Self::Error> {
+            self.0.lock().await.contains(key).await
+        }
+
+        async fn get(&self, key: TransactionId) -> Result<Option<TransactionState>, Self::Error> {
+            self.0.lock().await.get(key).await
+        }
+
+        async fn insert(
+            &mut self,
+            key: TransactionId,
+            state: TransactionState,
+        ) -> Result<(), Self::Error> {
+            self.0.lock().await.insert(key, state).await
+        }
+
+        async fn remove(&mut self, key: TransactionId) -> Result<bool, Self::Error> {
+            self.0.lock().await.remove(key).await
+        }
+
+        async fn clear(&mut self) -> Result<bool, Self::Error> {
+            self.0.lock().await.clear().await
+        }
+
+        async fn clear_owned(&mut self, owner: ClientId) -> Result<bool, Self::Error> {
+            self.0.lock().await.clear_owned(owner).await
+        }
+    }
+
+    #[tokio::test]
+    async fn transaction_owner() {
+        for check_transaction_owner in [true, false] {
+            let ledger = SharedLedger(std::sync::Arc::new(tokio::sync::Mutex::new(
+                InMemoryLedger::connect().unwrap(),
+            )));
+            let policy = AccountPolicy {
+                check_transaction_owner,
+                ..AccountPolicy::default()
+            };
+            let mut alice =
+                Account::with_policy(ledger.clone(), policy).with_owner(ClientId::from(1));
+            let mut bob =
+                Account::with_policy(ledger.clone(), policy).with_owner(ClientId::from(2));
+            let ten = Amount::from_str("10").unwrap();
+            let (id1, id2, id3) = (
+                TransactionId::from(1),
+                TransactionId::from(2),
+                TransactionId::from(3),
+            );
+            for (account, id) in [(&mut alice, id1), (&mut bob, id2)] {
+                assert_eq!(
+                    account
+                        .execute(Action::Transact((id, Transaction::Deposit(ten)), None))
+                        .await,
+                    Ok(())
+                );
+            }
+            //the ids are unique in the shared ledger
+            assert_eq!(
+                bob.execute(Action::Transact((id1, Transaction::Deposit(ten)), None))
+                    .await,
+                Err(TransactionError::RepeatedTransactionId)
+            );
+
+            let refused = if check_transaction_owner {
+                Err(TransactionError::NotTransactionOwner)
+            } else {
+                Ok(())
+            };
+            //bob disputes the deposit of alice
+            assert_eq!(bob.execute(Action::Dispute(id1)).await, refused);
+            if check_transaction_owner {
+                assert_eq!(bob.held(), Amount::ZERO);
+                assert_eq!(alice.execute(Action::Dispute(id1)).await, Ok(()));
+                for action in [
+                    Action::Resolve(id1),
+                    Action::ChargeBack(id1),
+                    Action::ReverseDeposit(id1),
+                ] {
+                    assert_eq!(bob.execute(action.clone()).await, refused, "{action:?}");
+                }
+                assert_eq!(alice.execute(Action::ChargeBack(id1)).await, Ok(()));
+                assert!(alice.is_locked());
+                assert!(!bob.is_locked());
+                //unknown ids are still reported as such
+                assert_eq!(
+                    bob.execute(Action::Dispute(id3)).await,
+                    Err(TransactionError::InvalidTransactionId)
+                );
+                //own transactions are not affected
+                assert_eq!(bob.execute(Action::Dispute(id2)).await, Ok(()));
+                assert_eq!(bob.held(), ten);
+                //the owner is stored in the ledger, so it is known by a restarted account too
+                let mut restarted =
+                    Account::with_policy(ledger.clone(), policy).with_owner(ClientId::from(2));
+                assert_eq!(restarted.execute(Action::Resolve(id2)).await, Ok(()));
+                //an account without owner owns nothing
+                let mut anonymous = Account::with_policy(ledger, policy);
+                assert_eq!(
+                    anonymous.execute(Action::Dispute(id2)).await,
+                    Err(TransactionError::NotTransactionOwner)
+                );
+            } else {
+                //without the check the funds of alice's deposit are held at bob
+                assert_eq!(bob.held(), ten);
+            }
+        }
+    }
+
+    #[cfg(feature = "dangerous-admin")]
+    #[tokio::test]
+    async fn reset_shared_ledger() {
+        let ledger = SharedLedger(std::sync::Arc::new(tokio::sync::Mutex::new(
+            InMemoryLedger::connect().unwrap(),
+        )));
+        let policy = AccountPolicy {
+            check_transaction_owner: true,
+            ..AccountPolicy::default()
+        };
+        let mut alice = Account::with_policy(ledger.clone(), policy).with_owner(ClientId::from(1));
+        let mut bob = Account::with_policy(ledger.clone(), policy).with_owner(ClientId::from(2));
+        let deposit = Transaction::Deposit(Amount::ONE);
+        let (id1, id2) = (TransactionId::from(1), TransactionId::from(2));
+        assert_eq!(
+            alice.execute(Action::Transact((id1, deposit), None)).await,
+            Ok(())
+        );
+        assert_eq!(
+            bob.execute(Action::Transact((id2, deposit), None)).await,
+            Ok(())
+        );
+
+        //only the transactions of bob are wiped
+        assert_eq!(bob.execute(Action::Reset).await, Ok(()));
+        assert_eq!(bob.total(), Amount::ZERO);
+        assert_eq!(bob.transaction_state(id2).await, Ok(None));
+        assert!(alice.transaction_state(id1).await.unwrap().is_some());
+        assert_eq!(alice.execute(Action::Dispute(id1)).await, Ok(()));
+
+        //an account without owner can not tell its transactions apart
+        let mut anonymous = Account::with_policy(ledger, policy);
+        assert_eq!(
+            anonymous.execute(Action::Reset).await,
+            Err(TransactionError::ResetUnsupported)
+        );
+        assert!(alice.transaction_state(id1).await.unwrap().is_some());
+    }
+
+    /// Generator of valid action sequences: disputes, resolves and charge backs refer to
+    /// previously deposited transactions (by index, modulo the number of deposits so far)
+    #[derive(Debug, Clone)]
+    enum Step {
+        Deposit(i64),
+        Withdraw(i64),
+        Dispute(usize),
+        Resolve(usize),
+        ChargeBack(usize),
+        Reverse(usize),
+    }
+
+    fn step() -> impl proptest::strategy::Strategy<Value = Step> {
+        use proptest::prelude::*;
+        //amounts in 1/10000 units, zero is included as an invalid amount
+        prop_oneof![
+            4 => (0..1_000_000i64).prop_map(Step::Deposit),
+            3 => (0..1_000_000i64).prop_map(Step::Withdraw),
+            2 => any::<usize>().prop_map(Step::Dispute),
+            2 => any::<usize>().prop_map(Step::Resolve),
+            1 => any::<usize>().prop_map(Step::ChargeBack),
+            1 => any::<usize>().prop_map(Step::Reverse),
+        ]
+    }
+
+    fn amount_of(units: i64) -> Amount {
+        Amount::from_str(&format!("{}.{:04}", units / 10000, units % 10000)).unwrap()
+    }
+
+    async fn check_invariants(steps: Vec<Step>) {
+        let mut account = connect();
+        let mut deposits = Vec::<TransactionId>::new();
+        let mut charged_back = false;
+        for (i, step) in steps.into_iter().enumerate() {
+            let id = TransactionId::from(i as u32);
+            let deposited = |index: usize| deposits.get(index % deposits.len().max(1)).copied();
+            let action = match step {
+                Step::Deposit(units) => {
+                    Action::Transact((id, Transaction::Deposit(amount_of(units))), None)
+                }
+                Step::Withdraw(units) => {
+                    Action::Transact((id, Transaction::Withdrawal(amount_of(units))), None)
+                }
+                Step::Dispute(index) => Action::Dispute(deposited(index).unwrap_or(id)),
+                Step::Resolve(index) => Action::Resolve(deposited(index).unwrap_or(id)),
+                Step::ChargeBack(index) => Action::ChargeBack(deposited(index).unwrap_or(id)),
+                Step::Reverse(index) => Action::ReverseDeposit(deposited(index).unwrap_or(id)),
+            };
+
+            let (total, held, locked) = (account.total(), account.held(), account.is_locked());
+            let result = account.execute(action.clone()).await;
+            if result.is_ok() {
+                if let Action::Transact((id, Transaction::Deposit(_)), _) = action {
+                    deposits.push(id);
+                }
+                if let Action::ChargeBack(_) = action {
+                    charged_back = true;
+                }
+            }
+
+            if !charged_back {
+                assert!(account.held() >= Amount::ZERO);
+            }
+            //no overflow is possible with these amounts, so available is never clamped
+            assert_eq!(account.available() + account.held(), account.total());
+            assert_eq!(account.is_locked(), charged_back);
+            if locked {
+                //locked accounts refuse every balance changing action of the client
+                //(the dispute workflow of earlier deposits can still go on, by design)
+                if let Action::Transact(..) | Action::ReverseDeposit(_) = action {
+                    assert!(result.is_err());
+                    assert_eq!(account.total(), total);
+                    assert_eq!(account.held(), held);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn balance_conservation_property() {
+        use proptest::collection::vec;
+        use proptest::test_runner::{Config, RngAlgorithm, TestRng, TestRunner};
+
+        //fixed seed, so the generated cases are the same in every run
+        let config = Config {
+            cases: 256,
+            failure_persistence: None,
+            ..Config::default()
+        };
+        let mut runner =
+            TestRunner::new_with_rng(config, TestRng::from_seed(RngAlgorithm::ChaCha, &[42; 32]));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .unwrap();
+        runner
+            .run(&vec(step(), 1..64), |steps| {
+                runtime.block_on(check_invariants(steps));
+                Ok(())
+            })
+            .unwrap();
+    }
 }
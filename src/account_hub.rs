@@ -2,22 +2,49 @@
 /// * it is the owner of all Accounts, does lifetime management
 /// * it is responsible to forward requests to the right Account actor
 use std::cmp::Ord;
-use std::collections::BTreeMap;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fmt;
 use std::fmt::Display;
 use std::str::FromStr;
-use tokio::sync::mpsc::error::SendError;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use tokio::sync::mpsc::error::{SendError, TrySendError};
 use tokio::sync::mpsc::{self, Sender};
+use tokio::sync::{oneshot, Semaphore};
 use tokio::task::JoinHandle;
 
-use log::{error, log_enabled};
+use log::error;
+use serde::Deserialize;
 
 pub use crate::account::*;
+use crate::summary::{AccountSummary, Summaries};
 
 /// Client ids wrapped in new type to avoid mixing them with other ids.
 /// Used to address the accounts managed by AccountHub.
 #[derive(Debug, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Hash)]
 pub struct ClientId(u16);
 
+impl ClientId {
+    pub const MIN: ClientId = ClientId(u16::MIN);
+    pub const MAX: ClientId = ClientId(u16::MAX);
+
+    /// Iterates over the client ids from 'lo' to 'hi' (inclusive)
+    pub fn range(lo: ClientId, hi: ClientId) -> impl Iterator<Item = ClientId> {
+        (lo.0..=hi.0).map(ClientId)
+    }
+
+    /// The following client id (None after 'MAX')
+    pub fn checked_next(self) -> Option<ClientId> {
+        self.0.checked_add(1).map(ClientId)
+    }
+
+    /// Binary representation (for storage)
+    pub fn to_bytes(self) -> [u8; 2] {
+        self.0.to_be_bytes()
+    }
+}
+
 impl From<u16> for ClientId {
     fn from(v: u16) -> Self {
         ClientId(v)
@@ -38,13 +65,194 @@ impl FromStr for ClientId {
     }
 }
 
+/// What to do when a client exceeds the configured transaction count limit
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LimitResponse {
+    /// flag the client and refuse its further actions, the others are processed normally
+    #[default]
+    RefuseClient,
+    /// refuse every further action of every client, the whole run should be stopped
+    AbortRun,
+}
+
+/// What 'AccountHub::summarize' does with the accounts which never received any action
+/// (e.g. the ones created by 'AccountHub::register')
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum IdleAccounts {
+    /// returned with zero balances
+    #[default]
+    Zeroed,
+    /// left out of the summary
+    Omit,
+}
+
+/// Reasons of AccountHub refusing to forward an action to an account
+#[derive(Debug)]
+pub enum ExecuteError {
+    /// the task of the account is not running anymore
+    Send(SendError<Action>),
+    /// the client has more actions than the configured limit
+    TransactionLimitExceeded(ClientId),
+    /// an earlier limit violation aborted the run
+    Aborted,
+    /// a transfer was refused by one of the accounts
+    Transfer(TransactionError),
+    /// strict mode: the client is not on the whitelist, no account is created for it
+    UnknownClient(ClientId),
+    /// the hub is already summarized, it accepts no more actions
+    Closed,
+}
+
+impl Display for ExecuteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExecuteError::Send(err) => write!(f, "account is not running ({err})"),
+            ExecuteError::TransactionLimitExceeded(client_id) => {
+                write!(f, "client {client_id} exceeded the transaction count limit")
+            }
+            ExecuteError::Aborted => write!(f, "processing aborted due to a limit violation"),
+            ExecuteError::Transfer(err) => write!(f, "transfer refused: {err}"),
+            ExecuteError::UnknownClient(client_id) => {
+                write!(f, "client {client_id} is not on the whitelist")
+            }
+            ExecuteError::Closed => write!(f, "the accounts are already summarized"),
+        }
+    }
+}
+
+impl Error for ExecuteError {}
+
+impl From<SendError<Action>> for ExecuteError {
+    fn from(err: SendError<Action>) -> Self {
+        ExecuteError::Send(err)
+    }
+}
+
+/// The outcome of an action executed on the account of a client, sent back on the response channel.
+pub type Response = (Result<(), TransactionError>, (ClientId, Action));
+
+/// The outcome of an action executed by 'AccountHub::execute_traced'
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TracedOutcome {
+    pub result: Result<(), TransactionError>,
+    /// the state of the traced transaction after the action (None if it is not in the ledger)
+    pub state: Option<TransactionState>,
+    /// the balances of the account after the action
+    pub balances: AccountSummary,
+}
+
+/// Steps of a transfer, executed by the task of the source or the destination account
+#[derive(Debug, Clone, Copy)]
+enum TransferStep {
+    CheckCredit,
+    Debit,
+    Credit,
+    /// undoes the debit if the credit failed
+    Refund,
+}
+
+/// Messages processed by the task of an account
+#[derive(Debug)]
+enum Request {
+    /// the result of the action goes to the response channel
+    Execute(Action),
+    /// the result of the step is sent back to the hub
+    Transfer(
+        TransferStep,
+        Action,
+        oneshot::Sender<Result<(), TransactionError>>,
+    ),
+    /// the result goes to the response channel, and it is sent back to the hub together with
+    /// the state of the given transaction and the balances after the action
+    Traced(Action, TransactionId, oneshot::Sender<TracedOutcome>),
+}
+
+impl Request {
+    fn action(&self) -> &Action {
+        match self {
+            Request::Execute(action)
+            | Request::Transfer(_, action, _)
+            | Request::Traced(action, _, _) => action,
+        }
+    }
+
+    fn into_action(self) -> Action {
+        match self {
+            Request::Execute(action)
+            | Request::Transfer(_, action, _)
+            | Request::Traced(action, _, _) => action,
+        }
+    }
+}
+
+/// The channel to send requests to the task of an account, and the handle of that task.
+/// The task returns the account, and whether it received any request
+type AccountTask<L> = (Sender<Request>, JoinHandle<((ClientId, Account<L>), bool)>);
+
+/// The settings of the hub (set by its 'with_*' calls), read by its handles at every action
+#[derive(Debug, Default)]
+struct Settings {
+    policy: AccountPolicy,
+    transaction_limit: Option<(u64, LimitResponse)>,
+    /// bounds the number of account tasks processing actions at the same time
+    permits: Option<Arc<Semaphore>>,
+    /// strict mode: only these clients may have accounts
+    whitelist: Option<Arc<BTreeSet<ClientId>>>,
+    /// nothing is logged while it is set (shared with the account tasks and the caller)
+    quiet: Arc<AtomicBool>,
+}
+
+/// The state of the hub shared with its handles
+#[derive(Debug)]
+struct Shared<L> {
+    settings: RwLock<Settings>,
+    accounts: Mutex<BTreeMap<ClientId, AccountTask<L>>>,
+    /// the clients in the order of their account creation (updated under the lock of 'accounts')
+    first_seen: Mutex<Vec<ClientId>>,
+    /// number of actions received per client (counted only if there is a limit)
+    transaction_counts: Mutex<BTreeMap<ClientId, u64>>,
+    flagged: Mutex<BTreeSet<ClientId>>,
+    aborted: AtomicBool,
+    /// set by 'summarize', no more accounts are accepted after it
+    closed: AtomicBool,
+    /// total weight of the actions sent to the accounts, but not processed yet
+    /// (the counters are shared with the account tasks too)
+    queued_weight: Arc<AtomicUsize>,
+    /// number of responses not sent because the response channel was full
+    dropped_responses: Arc<AtomicU64>,
+}
+
 /// Owner of client accounts, entry point to access them.
+/// More producers can push actions concurrently through its 'handle's.
 #[derive(Debug)]
 pub struct AccountHub<L> {
-    accounts: BTreeMap<ClientId, (Sender<Action>, JoinHandle<(ClientId, Account<L>)>)>,
+    handle: HubHandle<L>,
+    idle_accounts: IdleAccounts,
+}
+
+/// A cloneable handle of an 'AccountHub', to execute actions from more tasks concurrently.
+/// The actions of a client sent through the same handle (from the same task) are executed in order,
+/// the order of actions sent concurrently is decided by their arrival.
+/// The settings of the hub are shared with the handles, so the 'with_*' calls affect the existing handles too
+/// (but not the already created accounts).
+/// After 'AccountHub::summarize' the handles refuse every action with 'Closed'.
+#[derive(Debug)]
+pub struct HubHandle<L> {
+    shared: Arc<Shared<L>>,
     ledger_connector: fn(ClientId) -> Option<L>,
 }
 
+impl<L> Clone for HubHandle<L> {
+    fn clone(&self) -> Self {
+        HubHandle {
+            shared: self.shared.clone(),
+            ledger_connector: self.ledger_connector,
+        }
+    }
+}
+
 impl<L> AccountHub<L>
 where
     L: Ledger + 'static,
@@ -54,12 +262,119 @@ where
     /// This way easy to switch ledger implementations.
     pub fn new(ledger_connector: fn(ClientId) -> Option<L>) -> Self {
         AccountHub {
-            accounts:
-                BTreeMap::<ClientId, (Sender<Action>, JoinHandle<(ClientId, Account<L>)>)>::new(),
-            ledger_connector,
+            handle: HubHandle {
+                shared: Arc::new(Shared {
+                    settings: RwLock::new(Settings::default()),
+                    accounts: Mutex::new(BTreeMap::new()),
+                    first_seen: Mutex::new(Vec::new()),
+                    transaction_counts: Mutex::new(BTreeMap::new()),
+                    flagged: Mutex::new(BTreeSet::new()),
+                    aborted: AtomicBool::new(false),
+                    closed: AtomicBool::new(false),
+                    queued_weight: Arc::new(AtomicUsize::new(0)),
+                    dropped_responses: Arc::new(AtomicU64::new(0)),
+                }),
+                ledger_connector,
+            },
+            idle_accounts: IdleAccounts::default(),
         }
     }
 
+    /// A new handle to execute actions on the accounts of this hub (e.g. from other tasks)
+    pub fn handle(&self) -> HubHandle<L> {
+        self.handle.clone()
+    }
+
+    fn settings_mut(&mut self) -> RwLockWriteGuard<'_, Settings> {
+        self.handle
+            .shared
+            .settings
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// As an anti-abuse guard, limits the number of actions (of any kind) a single client may have.
+    /// The action exceeding the limit is refused with 'TransactionLimitExceeded', what happens next
+    /// depends on the given 'response'.
+    pub fn with_transaction_limit(mut self, limit: u64, response: LimitResponse) -> Self {
+        self.settings_mut().transaction_limit = Some((limit, response));
+        self
+    }
+
+    /// Limits the number of account tasks processing actions concurrently
+    /// (the number of accounts is not limited, the rest of the tasks wait for a permit).
+    /// Panics if 'permits' is zero.
+    pub fn with_task_permits(mut self, permits: usize) -> Self {
+        assert!(permits > 0, "at least one task permit is needed");
+        self.settings_mut().permits = Some(Arc::new(Semaphore::new(permits)));
+        self
+    }
+
+    /// How the accounts which never received any action are summarized
+    pub fn with_idle_accounts(mut self, idle_accounts: IdleAccounts) -> Self {
+        self.idle_accounts = idle_accounts;
+        self
+    }
+
+    /// Strict mode: accounts are created only for the given (closed set of) clients,
+    /// actions of any other client are refused with 'UnknownClient'.
+    pub fn with_client_whitelist<I: IntoIterator<Item = ClientId>>(mut self, clients: I) -> Self {
+        self.settings_mut().whitelist = Some(Arc::new(clients.into_iter().collect()));
+        self
+    }
+
+    /// Nothing is logged by the hub and its accounts while the given flag is set, regardless of
+    /// the log level filter (the flag is shared with the caller, so it can be changed at runtime)
+    pub fn with_quiet_flag(mut self, quiet: Arc<AtomicBool>) -> Self {
+        self.settings_mut().quiet = quiet;
+        self
+    }
+
+    /// Accounts created from now on will behave according to the given policy.
+    pub fn with_account_policy(mut self, policy: AccountPolicy) -> Self {
+        self.settings_mut().policy = policy;
+        self
+    }
+
+    /// Accounts created from now on will keep the amounts of their accepted deposits,
+    /// so the median can be calculated (see 'AccountPolicy::buffer_deposit_amounts')
+    pub fn with_buffered_deposit_amounts(mut self) -> Self {
+        self.settings_mut().policy.buffer_deposit_amounts = true;
+        self
+    }
+
+    /// The total weight (see 'Action::weight') of the actions waiting for execution in the queues of the accounts
+    pub fn queued_weight(&self) -> usize {
+        self.handle.queued_weight()
+    }
+
+    /// Whether logging is turned off (see 'with_quiet_flag')
+    pub fn is_quiet(&self) -> bool {
+        self.handle.is_quiet()
+    }
+
+    /// The number of responses dropped because the response channel was full.
+    /// (The responses are sent without waiting, so a slow consumer never slows down the processing.)
+    pub fn dropped_responses(&self) -> u64 {
+        self.handle.dropped_responses()
+    }
+
+    /// Clients which exceeded the transaction count limit
+    pub fn flagged_clients(&self) -> BTreeSet<ClientId> {
+        self.handle.flagged_clients()
+    }
+
+    /// The clients having accounts, in the order of their first appearance
+    /// (the first action or registration which created their account)
+    pub fn clients_by_first_seen(&self) -> Vec<ClientId> {
+        self.handle.clients_by_first_seen()
+    }
+
+    /// Whether a limit violation aborted the run (all further actions are refused)
+    pub fn is_aborted(&self) -> bool {
+        self.handle.is_aborted()
+    }
+
     /// Forwards the given action request message to the account addressed by client_id.
     /// If it not exists yet, a new account is created automatically by the lambda function
     /// passed to the AccountHub::new
@@ -67,61 +382,1322 @@ where
         &mut self,
         client_id: ClientId,
         action: Action,
-        response_sender: &Sender<(Result<(), TransactionError>, (ClientId, Action))>,
-    ) -> Result<(), SendError<Action>> {
-        if let Some((action_sender, _join_handle)) = self.accounts.get(&client_id) {
-            //if the client is already known, simply send the action for processing by his account
-            action_sender.send(action).await
+        response_sender: &Sender<Response>,
+    ) -> Result<(), ExecuteError> {
+        self.handle
+            .execute(client_id, action, response_sender)
+            .await
+    }
+
+    /// Same as 'execute', but waits for the execution of the action (see 'HubHandle::execute_traced')
+    pub async fn execute_traced(
+        &mut self,
+        client_id: ClientId,
+        action: Action,
+        traced: TransactionId,
+        response_sender: &Sender<Response>,
+    ) -> Result<TracedOutcome, ExecuteError> {
+        self.handle
+            .execute_traced(client_id, action, traced, response_sender)
+            .await
+    }
+
+    /// Creates a zeroed account for the client (if it not exists yet) without any action,
+    /// so it appears in the summary. Returns true if the client has an account.
+    /// (In strict mode no account is created for clients missing from the whitelist.)
+    pub fn register(&mut self, client_id: ClientId, response_sender: &Sender<Response>) -> bool {
+        self.handle.register(client_id, response_sender)
+    }
+
+    /// Returns the state of accounts after all actions executed.
+    /// Consumes self - this way blocks sending further actions for execution
+    /// (the handles are closed too).
+    pub async fn summarize(self) -> Vec<(ClientId, Account<L>)> {
+        let tasks = {
+            let mut accounts = self.handle.accounts();
+            self.handle.shared.closed.store(true, Ordering::Relaxed);
+            std::mem::take(&mut *accounts)
+        };
+        let mut accounts = Vec::<(ClientId, Account<L>)>::new();
+        //luckily the BTreeMap is sorted by key, so always produces the same result (good for unit tests).
+        for (_client, (sender, join_handle)) in tasks {
+            //drop the sender of every account -> they will exit from their spawned task and returning summary
+            drop(sender);
+            //the task of an account without any request exits right away (its channel is empty and closed)
+            match join_handle.await {
+                Ok((account, received))
+                    if received || self.idle_accounts == IdleAccounts::Zeroed =>
+                {
+                    accounts.push(account)
+                }
+                _ => {}
+            }
+        }
+        accounts
+    }
+
+    /// Same as 'summarize', but returns only the balances of the accounts, indexed by client
+    pub async fn summaries(self) -> Summaries {
+        Summaries::of_accounts(&self.summarize().await)
+    }
+}
+
+impl<L> HubHandle<L>
+where
+    L: Ledger + 'static,
+{
+    /// The current settings of the hub (the lock is never held across an await)
+    fn settings(&self) -> RwLockReadGuard<'_, Settings> {
+        self.shared
+            .settings
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// The accounts (the lock is never held across an await)
+    fn accounts(&self) -> MutexGuard<'_, BTreeMap<ClientId, AccountTask<L>>> {
+        //a panic while holding the lock can not leave the map inconsistent
+        self.shared
+            .accounts
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Whether logging is turned off (see 'AccountHub::with_quiet_flag')
+    pub fn is_quiet(&self) -> bool {
+        self.settings().quiet.load(Ordering::Relaxed)
+    }
+
+    /// Whether the client may have an account (always true, unless in strict mode)
+    fn is_known(&self, client_id: ClientId) -> bool {
+        self.settings()
+            .whitelist
+            .as_ref()
+            .is_none_or(|whitelist| whitelist.contains(&client_id))
+    }
+
+    /// The total weight (see 'Action::weight') of the actions waiting for execution in the queues of the accounts
+    pub fn queued_weight(&self) -> usize {
+        self.shared.queued_weight.load(Ordering::Relaxed)
+    }
+
+    /// The number of responses dropped because the response channel was full.
+    pub fn dropped_responses(&self) -> u64 {
+        self.shared.dropped_responses.load(Ordering::Relaxed)
+    }
+
+    /// Sends the request to the task of an account, accounting its weight until it is processed
+    async fn send(
+        &self,
+        request_sender: &Sender<Request>,
+        request: Request,
+    ) -> Result<(), ExecuteError> {
+        let weight = request.action().weight();
+        let queued_weight = &self.shared.queued_weight;
+        queued_weight.fetch_add(weight, Ordering::Relaxed);
+        request_sender.send(request).await.map_err(|err| {
+            queued_weight.fetch_sub(weight, Ordering::Relaxed);
+            ExecuteError::Send(SendError(err.0.into_action()))
+        })
+    }
+
+    /// Clients which exceeded the transaction count limit
+    pub fn flagged_clients(&self) -> BTreeSet<ClientId> {
+        self.shared
+            .flagged
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Whether a limit violation aborted the run (all further actions are refused)
+    pub fn is_aborted(&self) -> bool {
+        self.shared.aborted.load(Ordering::Relaxed)
+    }
+
+    /// The clients having accounts, in the order of their first appearance
+    pub fn clients_by_first_seen(&self) -> Vec<ClientId> {
+        self.shared
+            .first_seen
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .clone()
+    }
+
+    /// Counts the action of the client against the transaction limit (if any)
+    fn check_transaction_limit(&self, client_id: ClientId) -> Result<(), ExecuteError> {
+        if self.is_aborted() {
+            return Err(ExecuteError::Aborted);
+        }
+        let transaction_limit = self.settings().transaction_limit;
+        if let Some((limit, response)) = transaction_limit {
+            let mut counts = self
+                .shared
+                .transaction_counts
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner);
+            let count = counts.entry(client_id).or_insert(0);
+            *count += 1;
+            if *count > limit {
+                match response {
+                    LimitResponse::RefuseClient => {
+                        self.shared
+                            .flagged
+                            .lock()
+                            .unwrap_or_else(PoisonError::into_inner)
+                            .insert(client_id);
+                    }
+                    LimitResponse::AbortRun => self.shared.aborted.store(true, Ordering::Relaxed),
+                }
+                return Err(ExecuteError::TransactionLimitExceeded(client_id));
+            }
+        }
+        Ok(())
+    }
+
+    /// Forwards the given action request message to the account addressed by client_id
+    /// (see 'AccountHub::execute').
+    pub async fn execute(
+        &self,
+        client_id: ClientId,
+        action: Action,
+        response_sender: &Sender<Response>,
+    ) -> Result<(), ExecuteError> {
+        if !self.is_known(client_id) {
+            return Err(ExecuteError::UnknownClient(client_id));
+        }
+        self.check_transaction_limit(client_id)?;
+
+        if let Action::Transfer {
+            id,
+            from,
+            to,
+            amount,
+        } = action
+        {
+            if from != client_id {
+                return Err(ExecuteError::Transfer(
+                    TransactionError::TransferSourceMismatch,
+                ));
+            }
+            return self.transfer(id, from, to, amount, response_sender).await;
+        }
+
+        match self.request_sender(client_id, response_sender)? {
+            Some(request_sender) => self.send(&request_sender, Request::Execute(action)).await,
+            None => {
+                if !self.is_quiet() {
+                    error!(
+                        "Transaction refused: Database connection failed (client: {client_id} {:?})",
+                        action
+                    );
+                }
+                Ok(())
+            }
+        }
+    }
+
+    /// Same as 'execute', but waits for the execution of the action, and returns its result with
+    /// the state of the 'traced' transaction and the balances of the account right after it
+    /// (for debugging, it is slow). Transfers can not be traced, those are refused.
+    pub async fn execute_traced(
+        &self,
+        client_id: ClientId,
+        action: Action,
+        traced: TransactionId,
+        response_sender: &Sender<Response>,
+    ) -> Result<TracedOutcome, ExecuteError> {
+        if !self.is_known(client_id) {
+            return Err(ExecuteError::UnknownClient(client_id));
+        }
+        if let Action::Transfer { .. } = action {
+            return Err(ExecuteError::Transfer(
+                TransactionError::InvalidTransactionType,
+            ));
+        }
+        self.check_transaction_limit(client_id)?;
+
+        match self.request_sender(client_id, response_sender)? {
+            Some(request_sender) => {
+                let (outcome_sender, outcome_receiver) = oneshot::channel();
+                self.send(
+                    &request_sender,
+                    Request::Traced(action.clone(), traced, outcome_sender),
+                )
+                .await?;
+                outcome_receiver
+                    .await
+                    .map_err(|_| ExecuteError::Send(SendError(action)))
+            }
+            None => {
+                if !self.is_quiet() {
+                    error!(
+                        "Transaction refused: Database connection failed (client: {client_id} {:?})",
+                        action
+                    );
+                }
+                Err(ExecuteError::Send(SendError(action)))
+            }
+        }
+    }
+
+    /// The channel to the task of the account of the client.
+    /// If it not exists yet, a new account is created (None if its ledger connection failed).
+    fn request_sender(
+        &self,
+        client_id: ClientId,
+        response_sender: &Sender<Response>,
+    ) -> Result<Option<Sender<Request>>, ExecuteError> {
+        //the lock is held until the new account is stored, so concurrent handles can not create it twice
+        let mut accounts = self.accounts();
+        if self.shared.closed.load(Ordering::Relaxed) {
+            return Err(ExecuteError::Closed);
+        }
+        if let Some((request_sender, _join_handle)) = accounts.get(&client_id) {
+            return Ok(Some(request_sender.clone()));
+        }
+        Ok(self
+            .new_account(client_id)
+            .map(|account| self.insert_account(&mut accounts, client_id, account, response_sender)))
+    }
+
+    /// A new account of the client (None if its ledger connection failed)
+    fn new_account(&self, client_id: ClientId) -> Option<Account<L>> {
+        (self.ledger_connector)(client_id).map(|ledger| {
+            Account::with_policy(ledger, self.settings().policy).with_owner(client_id)
+        })
+    }
+
+    /// Spawns the task of the account and stores it, returns the channel to the task
+    fn insert_account(
+        &self,
+        accounts: &mut BTreeMap<ClientId, AccountTask<L>>,
+        client_id: ClientId,
+        account: Account<L>,
+        response_sender: &Sender<Response>,
+    ) -> Sender<Request> {
+        let (request_sender, join_handle) = self.spawn_account(client_id, account, response_sender);
+        accounts.insert(client_id, (request_sender.clone(), join_handle));
+        self.shared
+            .first_seen
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(client_id);
+        request_sender
+    }
+
+    /// Moves funds between two accounts. The steps are executed one after the other, each awaited:
+    /// 1. the destination is checked whether it could accept the funds,
+    /// 2. the source is debited (refused if the available funds are not enough),
+    /// 3. the destination is credited.
+    ///
+    /// A refused step leaves both accounts untouched. Step 3 can fail only on ledger errors
+    /// (or on closing the hub) after the checks passed: then the source is refunded and the error
+    /// of the credit is returned, or if the refund fails too, 'TransferPartiallyFailed'.
+    /// A destination without account is checked on a new account, which is stored (and appears
+    /// in the summary) only after the debit succeeded.
+    /// The hub waits only for one account at a time and the account tasks never wait for each
+    /// other, so the two action channels can not deadlock. Since the hub is the only sender,
+    /// actions of the two accounts sent before/after the transfer are executed before/after it.
+    /// (With more concurrent handles the actions of the other producers may get between the steps,
+    /// so step 3 can be refused also if those lock the destination in the meantime.)
+    async fn transfer(
+        &self,
+        id: TransactionId,
+        from: ClientId,
+        to: ClientId,
+        amount: Amount,
+        response_sender: &Sender<Response>,
+    ) -> Result<(), ExecuteError> {
+        if from == to {
+            return Err(ExecuteError::Transfer(TransactionError::SelfTransfer));
+        }
+        //no account is created for a source without account, it has no funds to transfer
+        if !self.accounts().contains_key(&from) {
+            return Err(ExecuteError::Transfer(TransactionError::InsufficientFunds));
+        }
+        if !self.is_known(to) {
+            return Err(ExecuteError::UnknownClient(to));
+        }
+
+        let action = Action::Transfer {
+            id,
+            from,
+            to,
+            amount,
+        };
+        let new_destination = if self.accounts().contains_key(&to) {
+            self.transfer_step(to, TransferStep::CheckCredit, &action)
+                .await?;
+            None
         } else {
-            //for new clients an account with a transaction database has to be created
-            //and on success send the first action for processing by his account
-            match (self.ledger_connector)(client_id) {
-                Some(ledger) => {
-                    let (action_sender, mut action_receiver) = mpsc::channel::<Action>(16);
-                    let mut account = Account::new(ledger);
-                    let responder = response_sender.clone(); //each spawned task has his own sender to the response channel
-
-                    // for each account spawn a task which processes his actions form the channel
-                    let join_handle: JoinHandle<_> = tokio::spawn(async move {
-                        while let Some(action) = action_receiver.recv().await {
-                            let response = account.execute(action).await;
-                            if log_enabled!(log::Level::Error) {
-                                let _err = responder.send((response, (client_id, action))).await;
-                            }
-                            //discard possible error
-                        }
+            let account = self
+                .new_account(to)
+                .ok_or(ExecuteError::Transfer(TransactionError::DbError))?;
+            account
+                .check_transfer_in(id, amount)
+                .await
+                .map_err(ExecuteError::Transfer)?;
+            Some(account)
+        };
+        self.transfer_step(from, TransferStep::Debit, &action)
+            .await?;
+        if let Some(account) = new_destination {
+            let mut accounts = self.accounts();
+            //(an account created by another handle in the meantime is credited instead)
+            if !self.shared.closed.load(Ordering::Relaxed) && !accounts.contains_key(&to) {
+                self.insert_account(&mut accounts, to, account, response_sender);
+            }
+        }
+        let credit_err = match self.transfer_step(to, TransferStep::Credit, &action).await {
+            Ok(()) => return Ok(()),
+            Err(err) => err,
+        };
+        match self
+            .transfer_step(from, TransferStep::Refund, &action)
+            .await
+        {
+            Ok(()) => Err(credit_err),
+            Err(_refund_err) => {
+                if !self.is_quiet() {
+                    error!(
+                        "Transfer partially failed: {credit_err}, refund failed: {_refund_err} (client: {from} {:?})",
+                        action
+                    );
+                }
+                Err(ExecuteError::Transfer(
+                    TransactionError::TransferPartiallyFailed,
+                ))
+            }
+        }
+    }
 
-                        (client_id, account)
-                    });
-                    let result = action_sender.send(action).await; //send the first action!
-                    self.accounts
-                        .insert(client_id, (action_sender, join_handle));
-                    result
+    /// Executes a step of a transfer by the account of the client and waits for its result
+    async fn transfer_step(
+        &self,
+        client_id: ClientId,
+        step: TransferStep,
+        action: &Action,
+    ) -> Result<(), ExecuteError> {
+        let request_sender = self
+            .accounts()
+            .get(&client_id)
+            .map(|(request_sender, _join_handle)| request_sender.clone())
+            .ok_or_else(|| ExecuteError::Send(SendError(action.clone())))?;
+        let (result_sender, result_receiver) = oneshot::channel();
+        self.send(
+            &request_sender,
+            Request::Transfer(step, action.clone(), result_sender),
+        )
+        .await?;
+        match result_receiver.await {
+            Ok(result) => result.map_err(ExecuteError::Transfer),
+            Err(_) => Err(ExecuteError::Send(SendError(action.clone()))),
+        }
+    }
+
+    /// Creates a zeroed account for the client (see 'AccountHub::register')
+    pub fn register(&self, client_id: ClientId, response_sender: &Sender<Response>) -> bool {
+        if !self.is_known(client_id) {
+            return false;
+        }
+        match self.request_sender(client_id, response_sender) {
+            Ok(Some(_request_sender)) => true,
+            Ok(None) => {
+                if !self.is_quiet() {
+                    error!(
+                        "Account registration failed: Database connection failed (client: {client_id})"
+                    );
+                }
+                false
+            }
+            Err(_closed) => false,
+        }
+    }
+
+    /// Spawns a task which processes the actions of the client on the given account
+    /// from the returned channel
+    fn spawn_account(
+        &self,
+        client_id: ClientId,
+        mut account: Account<L>,
+        response_sender: &Sender<Response>,
+    ) -> AccountTask<L> {
+        let (request_sender, mut request_receiver) = mpsc::channel::<Request>(16);
+        let responder = response_sender.clone(); //each spawned task has his own sender to the response channel
+        let permits = self.settings().permits.clone();
+        let queued_weight = self.shared.queued_weight.clone();
+        let dropped_responses = self.shared.dropped_responses.clone();
+        let quiet = self.settings().quiet.clone();
+
+        let join_handle: JoinHandle<_> = tokio::spawn(async move {
+            let respond = |response: Response| {
+                if let Err(TrySendError::Full(_)) = responder.try_send(response) {
+                    dropped_responses.fetch_add(1, Ordering::Relaxed);
                 }
-                _ => {
-                    error!("Transaction refused: Database connection failed (client: {client_id} {:?})", action);
-                    Ok(())
+                //discard possible error (a closed channel means nobody listens)
+            };
+            let mut received = false;
+            while let Some(request) = request_receiver.recv().await {
+                received = true;
+                //the permit is held only while the action is processed (idle accounts do not block the others)
+                let _permit = match &permits {
+                    Some(permits) => permits.acquire().await.ok(),
+                    None => None,
+                };
+                match request {
+                    Request::Execute(action) => {
+                        let response = account.execute(action.clone()).await;
+                        queued_weight.fetch_sub(action.weight(), Ordering::Relaxed);
+                        respond((response, (client_id, action)));
+                    }
+                    Request::Traced(action, traced, outcome_sender) => {
+                        let result = account.execute(action.clone()).await;
+                        queued_weight.fetch_sub(action.weight(), Ordering::Relaxed);
+                        respond((result, (client_id, action)));
+                        let outcome = TracedOutcome {
+                            result,
+                            //a ledger error is reported as a missing transaction
+                            state: account.transaction_state(traced).await.ok().flatten(),
+                            balances: AccountSummary::new(client_id, &account),
+                        };
+                        let _err = outcome_sender.send(outcome); //the hub may not wait any more
+                    }
+                    Request::Transfer(step, action, result_sender) => {
+                        let weight = action.weight();
+                        let result = match (step, action) {
+                            (TransferStep::CheckCredit, Action::Transfer { id, amount, .. }) => {
+                                account.check_transfer_in(id, amount).await
+                            }
+                            (TransferStep::Debit, Action::Transfer { id, amount, .. }) => {
+                                account.transfer_out(id, amount).await
+                            }
+                            (TransferStep::Credit, Action::Transfer { id, amount, .. }) => {
+                                account.transfer_in(id, amount).await
+                            }
+                            (TransferStep::Refund, Action::Transfer { id, amount, .. }) => {
+                                account.refund_transfer_out(id, amount).await
+                            }
+                            _ => Err(TransactionError::Unexpected),
+                        };
+                        queued_weight.fetch_sub(weight, Ordering::Relaxed);
+                        let _err = result_sender.send(result); //the hub may not wait any more
+                    }
                 }
             }
+
+            //all senders are dropped, so there are no more actions
+            if account.close().await.is_err() && !quiet.load(Ordering::Relaxed) {
+                error!("Closing the ledger failed (client: {client_id})");
+            }
+            ((client_id, account), received)
+        });
+        (request_sender, join_handle)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::in_memory_ledger::{InMemoryLedger, LedgerError};
+
+    fn deposit(id: u32, amount: &str) -> Action {
+        Action::Transact(
+            (
+                TransactionId::from(id),
+                Transaction::Deposit(Amount::from_str(amount).unwrap()),
+            ),
+            None,
+        )
+    }
+
+    fn withdrawal(id: u32, amount: &str) -> Action {
+        Action::Transact(
+            (
+                TransactionId::from(id),
+                Transaction::Withdrawal(Amount::from_str(amount).unwrap()),
+            ),
+            None,
+        )
+    }
+
+    #[test]
+    fn client_id_limits() {
+        assert_eq!(ClientId::MIN, ClientId::from(u16::MIN));
+        assert_eq!(ClientId::MAX, ClientId::from(u16::MAX));
+        assert_eq!(ClientId::MAX.0, u16::MAX);
+    }
+
+    #[test]
+    fn client_id_next() {
+        assert_eq!(ClientId::from(7).checked_next(), Some(ClientId::from(8)));
+        assert_eq!(ClientId::MIN.checked_next(), Some(ClientId::from(1)));
+        assert_eq!(
+            ClientId::from(u16::MAX - 1).checked_next(),
+            Some(ClientId::MAX)
+        );
+        assert_eq!(ClientId::MAX.checked_next(), None);
+    }
+
+    #[test]
+    fn client_id_range() {
+        let ids: Vec<_> = ClientId::range(ClientId::from(3), ClientId::from(6)).collect();
+        assert_eq!(
+            ids,
+            vec![
+                ClientId::from(3),
+                ClientId::from(4),
+                ClientId::from(5),
+                ClientId::from(6)
+            ]
+        );
+        assert_eq!(
+            ClientId::range(ClientId::from(7), ClientId::from(7)).count(),
+            1
+        );
+        assert_eq!(
+            ClientId::range(ClientId::from(8), ClientId::from(7)).count(),
+            0
+        );
+        assert_eq!(ClientId::range(ClientId::MIN, ClientId::MAX).count(), 65536);
+        assert_eq!(
+            ClientId::range(ClientId::MIN, ClientId::MAX).last(),
+            Some(ClientId::MAX)
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_limit_refusing_client() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect())
+            .with_transaction_limit(3, LimitResponse::RefuseClient);
+        let (client1, client2) = (ClientId::from(1), ClientId::from(2));
+        for id in 1..=3 {
+            hub.execute(client1, deposit(id, "1"), &response_sender)
+                .await
+                .unwrap();
         }
+        assert!(matches!(
+            hub.execute(client1, deposit(4, "1"), &response_sender).await,
+            Err(ExecuteError::TransactionLimitExceeded(client)) if client == client1
+        ));
+        hub.execute(client2, deposit(5, "1"), &response_sender)
+            .await
+            .unwrap();
+        assert!(!hub.is_aborted());
+        assert_eq!(
+            hub.flagged_clients().iter().collect::<Vec<_>>(),
+            vec![&client1]
+        );
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts[0].1.total(), Amount::from_str("3").unwrap());
+        assert_eq!(accounts[1].1.total(), Amount::from_str("1").unwrap());
     }
 
-    /// Returns the state of accounts after all actions executed.
-    /// Consumes self - this way blocks sending further actions for execution.
-    pub async fn summarize(mut self) -> Vec<(ClientId, Account<L>)> {
-        let mut accounts = Vec::<(ClientId, Account<L>)>::new();
-        //TODO Nightly has "pop_first"
-        //luckily the BTreeMap is sorted by key, so always produces the same result (good for unit tests).
-        let clients: Vec<_> = self.accounts.keys().cloned().collect();
-        for client in clients {
-            if let Some((sender, join_handle)) = self.accounts.remove(&client) {
-                //drop the sender of every account -> they will exit from their spawned task and returning summary
-                drop(sender);
-                if let Ok(account) = join_handle.await {
-                    accounts.push(account);
+    #[tokio::test]
+    async fn transaction_limit_aborting_run() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect())
+            .with_transaction_limit(3, LimitResponse::AbortRun);
+        let (client1, client2) = (ClientId::from(1), ClientId::from(2));
+        hub.execute(client2, deposit(1, "1"), &response_sender)
+            .await
+            .unwrap();
+        for id in 2..=4 {
+            hub.execute(client1, deposit(id, "1"), &response_sender)
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            hub.execute(client1, deposit(5, "1"), &response_sender).await,
+            Err(ExecuteError::TransactionLimitExceeded(client)) if client == client1
+        ));
+        assert!(hub.is_aborted());
+        assert!(matches!(
+            hub.execute(client2, deposit(6, "1"), &response_sender)
+                .await,
+            Err(ExecuteError::Aborted)
+        ));
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts[0].1.total(), Amount::from_str("3").unwrap());
+        assert_eq!(accounts[1].1.total(), Amount::from_str("1").unwrap());
+    }
+
+    #[tokio::test]
+    async fn unfunded_withdrawal_summarized_as_pending() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect()).with_account_policy(
+            AccountPolicy {
+                pending_withdrawals: true,
+                ..AccountPolicy::default()
+            },
+        );
+        let client = ClientId::from(1);
+        for action in [deposit(1, "10"), withdrawal(2, "25.5"), deposit(3, "5")] {
+            hub.execute(client, action, &response_sender).await.unwrap();
+        }
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts.len(), 1);
+        let (client_id, account) = &accounts[0];
+        assert_eq!(*client_id, client);
+        assert_eq!(account.total(), Amount::from_str("15").unwrap());
+        assert_eq!(account.pending(), Amount::from_str("25.5").unwrap());
+    }
+
+    #[tokio::test]
+    async fn registered_clients() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect());
+        let (client1, client2) = (ClientId::from(1), ClientId::from(2));
+        hub.execute(client1, deposit(1, "1"), &response_sender)
+            .await
+            .unwrap();
+        assert!(hub.register(client1, &response_sender));
+        assert!(hub.register(client2, &response_sender));
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].1.total(), Amount::ONE);
+        assert_eq!(accounts[1].0, client2);
+        assert_eq!(accounts[1].1.total(), Amount::ZERO);
+        assert_eq!(accounts[1].1.transaction_count(), 0);
+
+        let mut hub = AccountHub::<InMemoryLedger>::new(|_client_id| None);
+        assert!(!hub.register(client1, &response_sender));
+        assert!(hub.summarize().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn idle_accounts() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let (client1, client2, client3) = (ClientId::from(1), ClientId::from(2), ClientId::from(3));
+        for idle_accounts in [IdleAccounts::Zeroed, IdleAccounts::Omit] {
+            let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect())
+                .with_idle_accounts(idle_accounts);
+            hub.execute(client1, deposit(1, "1"), &response_sender)
+                .await
+                .unwrap();
+            //a refused action is still an action
+            hub.execute(
+                client3,
+                Action::Dispute(TransactionId::from(9)),
+                &response_sender,
+            )
+            .await
+            .unwrap();
+            assert!(hub.register(client2, &response_sender));
+            let accounts = tokio::time::timeout(std::time::Duration::from_secs(5), hub.summarize())
+                .await
+                .expect("summarize should not wait for accounts without actions");
+            let clients: Vec<_> = accounts.iter().map(|(client_id, _)| *client_id).collect();
+            match idle_accounts {
+                IdleAccounts::Zeroed => {
+                    assert_eq!(clients, vec![client1, client2, client3]);
+                    let idle = &accounts[1].1;
+                    assert_eq!(idle.available(), Amount::ZERO);
+                    assert_eq!(idle.held(), Amount::ZERO);
+                    assert_eq!(idle.total(), Amount::ZERO);
+                    assert!(!idle.is_locked());
+                    assert_eq!(idle.transaction_count(), 0);
                 }
+                IdleAccounts::Omit => assert_eq!(clients, vec![client1, client3]),
             }
         }
-        accounts
+
+        //only registered accounts
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect());
+        assert!(hub.register(client2, &response_sender));
+        let accounts = tokio::time::timeout(std::time::Duration::from_secs(5), hub.summarize())
+            .await
+            .unwrap();
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].1.total(), Amount::ZERO);
+    }
+
+    #[tokio::test]
+    async fn client_whitelist() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect())
+            .with_client_whitelist([ClientId::from(1), ClientId::from(2)]);
+        let (client1, client2, client3) = (ClientId::from(1), ClientId::from(2), ClientId::from(3));
+        hub.execute(client1, deposit(1, "1"), &response_sender)
+            .await
+            .unwrap();
+        hub.execute(client2, deposit(2, "2"), &response_sender)
+            .await
+            .unwrap();
+        assert!(matches!(
+            hub.execute(client3, deposit(3, "3"), &response_sender).await,
+            Err(ExecuteError::UnknownClient(client)) if client == client3
+        ));
+        assert!(!hub.register(client3, &response_sender));
+        assert!(matches!(
+            hub.execute(
+                client1,
+                Action::Transfer {
+                    id: TransactionId::from(4),
+                    from: client1,
+                    to: client3,
+                    amount: Amount::ONE
+                },
+                &response_sender
+            )
+            .await,
+            Err(ExecuteError::UnknownClient(client)) if client == client3
+        ));
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(
+            (accounts[0].0, accounts[0].1.total()),
+            (client1, Amount::ONE)
+        );
+        assert_eq!(
+            (accounts[1].0, accounts[1].1.total()),
+            (client2, Amount::from(2))
+        );
+    }
+
+    /// a ledger without simulated delays (the timing of the test must not depend on the features)
+    fn connect_without_delay(_client_id: ClientId) -> Option<InMemoryLedger> {
+        #[cfg(feature = "simulate-delays")]
+        return InMemoryLedger::connect_with_delay(std::time::Duration::ZERO);
+        #[cfg(not(feature = "simulate-delays"))]
+        InMemoryLedger::connect()
+    }
+
+    #[tokio::test]
+    async fn lossy_responses() {
+        //the receiver is never read, like a logger which fell behind
+        let (response_sender, response_receiver) = mpsc::channel::<Response>(1);
+        let mut hub = AccountHub::new(connect_without_delay);
+        let handle = hub.handle();
+        let accounts = tokio::time::timeout(std::time::Duration::from_secs(10), async {
+            for id in 1..=100 {
+                hub.execute(
+                    ClientId::from(id as u16 % 4),
+                    deposit(id, "1"),
+                    &response_sender,
+                )
+                .await
+                .unwrap();
+            }
+            hub.summarize().await
+        })
+        .await
+        .expect("the processing must not wait for the logger");
+
+        let total = accounts
+            .iter()
+            .fold(Amount::ZERO, |sum, (_, account)| sum + account.total());
+        assert_eq!(total, Amount::from(100));
+        assert_eq!(response_receiver.len(), 1);
+        assert_eq!(handle.dropped_responses(), 99);
+    }
+
+    #[tokio::test]
+    async fn handle_follows_settings() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let hub = AccountHub::new(|_client_id| InMemoryLedger::connect());
+        let handle = hub.handle();
+        let hub = hub
+            .with_client_whitelist([ClientId::from(1)])
+            .with_transaction_limit(2, LimitResponse::RefuseClient)
+            .with_account_policy(AccountPolicy {
+                max_balance: Some(Amount::from(10)),
+                ..AccountPolicy::default()
+            });
+
+        //the handle created before the 'with_*' calls applies them too
+        assert!(matches!(
+            handle
+                .execute(ClientId::from(2), deposit(1, "1"), &response_sender)
+                .await,
+            Err(ExecuteError::UnknownClient(client)) if client == ClientId::from(2)
+        ));
+        for (id, amount) in [(2, "11"), (3, "10")] {
+            handle
+                .execute(ClientId::from(1), deposit(id, amount), &response_sender)
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            handle
+                .execute(ClientId::from(1), deposit(4, "1"), &response_sender)
+                .await,
+            Err(ExecuteError::TransactionLimitExceeded(_))
+        ));
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts.len(), 1);
+        assert_eq!(accounts[0].1.total(), Amount::from(10));
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn concurrent_handles() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let hub = AccountHub::new(|_client_id| InMemoryLedger::connect());
+        let producers: Vec<_> = (0..4u32)
+            .map(|producer| {
+                let handle = hub.handle();
+                let response_sender = response_sender.clone();
+                tokio::spawn(async move {
+                    //every producer uses its own transaction ids for the same clients
+                    let mut id = producer * 1000;
+                    for round in 0..10 {
+                        for client in 1..=5 {
+                            id += 1;
+                            handle
+                                .execute(
+                                    ClientId::from(client),
+                                    deposit(id, "10"),
+                                    &response_sender,
+                                )
+                                .await
+                                .unwrap();
+                            //covered by the deposit above if the actions of a producer are executed in order
+                            id += 1;
+                            handle
+                                .execute(
+                                    ClientId::from(client),
+                                    withdrawal(id, &(round + 1).to_string()),
+                                    &response_sender,
+                                )
+                                .await
+                                .unwrap();
+                        }
+                    }
+                })
+            })
+            .collect();
+        for producer in producers {
+            producer.await.unwrap();
+        }
+
+        let handle = hub.handle();
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts.len(), 5);
+        for (_client_id, account) in accounts {
+            //4 producers * (10 * 10 - (1 + 2 + ... + 10))
+            assert_eq!(account.total(), Amount::from(4 * (100 - 55)));
+            assert_eq!(account.transaction_count(), 4 * 20);
+        }
+        assert!(matches!(
+            handle
+                .execute(ClientId::from(1), deposit(1, "1"), &response_sender)
+                .await,
+            Err(ExecuteError::Closed)
+        ));
+    }
+
+    static CLOSES: AtomicUsize = AtomicUsize::new(0);
+    static USED_AFTER_CLOSE: AtomicUsize = AtomicUsize::new(0);
+
+    /// counts the closes and the accesses after close
+    struct ClosingLedger {
+        inner: InMemoryLedger,
+        closed: bool,
+    }
+
+    impl ClosingLedger {
+        fn connect() -> Option<Self> {
+            InMemoryLedger::connect().map(|inner| ClosingLedger {
+                inner,
+                closed: false,
+            })
+        }
+
+        fn check_open(&self) {
+            if self.closed {
+                USED_AFTER_CLOSE.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+
+    #[async_trait::async_trait]
+    impl Ledger for ClosingLedger {
+        type Error = LedgerError;
+
+        async fn contains(&self, key: TransactionId) -> Result<bool, Self::Error> {
+            self.check_open();
+            self.inner.contains(key).await
+        }
+
+        async fn get(&self, key: TransactionId) -> Result<Option<TransactionState>, Self::Error> {
+            self.check_open();
+            self.inner.get(key).await
+        }
+
+        async fn insert(
+            &mut self,
+            key: TransactionId,
+            state: TransactionState,
+        ) -> Result<(), Self::Error> {
+            self.check_open();
+            self.inner.insert(key, state).await
+        }
+
+        async fn remove(&mut self, key: TransactionId) -> Result<bool, Self::Error> {
+            self.check_open();
+            self.inner.remove(key).await
+        }
+
+        async fn clear(&mut self) -> Result<bool, Self::Error> {
+            self.check_open();
+            self.inner.clear().await
+        }
+
+        async fn clear_owned(&mut self, owner: ClientId) -> Result<bool, Self::Error> {
+            self.check_open();
+            self.inner.clear_owned(owner).await
+        }
+
+        async fn close(&mut self) -> Result<(), Self::Error> {
+            self.check_open();
+            self.closed = true;
+            CLOSES.fetch_add(1, Ordering::Relaxed);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn ledgers_closed() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| ClosingLedger::connect());
+        for id in 1..=30 {
+            hub.execute(
+                ClientId::from(id as u16 % 3),
+                deposit(id, "1"),
+                &response_sender,
+            )
+            .await
+            .unwrap();
+        }
+        assert!(hub.register(ClientId::from(7), &response_sender));
+        assert_eq!(CLOSES.load(Ordering::Relaxed), 0);
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts.len(), 4);
+        assert_eq!(CLOSES.load(Ordering::Relaxed), 4);
+        assert_eq!(USED_AFTER_CLOSE.load(Ordering::Relaxed), 0);
+        assert_eq!(accounts[0].1.total(), Amount::from(10));
+    }
+
+    #[tokio::test]
+    async fn single_task_permit() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect()).with_task_permits(1);
+        let mut id = 0;
+        for round in 1..=10 {
+            for client in 1..=8 {
+                id += 1;
+                hub.execute(
+                    ClientId::from(client),
+                    deposit(id, &round.to_string()),
+                    &response_sender,
+                )
+                .await
+                .unwrap();
+            }
+        }
+        for client in 1..=8 {
+            id += 1;
+            hub.execute(
+                ClientId::from(client),
+                withdrawal(id, "5"),
+                &response_sender,
+            )
+            .await
+            .unwrap();
+        }
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts.len(), 8);
+        for (client, (client_id, account)) in (1..=8).zip(accounts) {
+            assert_eq!(client_id, ClientId::from(client));
+            assert_eq!(account.total(), Amount::from(50));
+            assert_eq!(account.transaction_count(), 11);
+        }
+    }
+
+    #[tokio::test]
+    async fn queued_weight() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect()).with_task_permits(1);
+        assert_eq!(hub.queued_weight(), 0);
+
+        //the account can not process anything while the only permit is held here
+        let permits = hub.handle.settings().permits.clone().unwrap();
+        let permit = permits.acquire_owned().await.unwrap();
+        let client = ClientId::from(1);
+        hub.execute(client, deposit(1, "1"), &response_sender)
+            .await
+            .unwrap();
+        hub.execute(
+            client,
+            Action::Dispute(TransactionId::from(1)),
+            &response_sender,
+        )
+        .await
+        .unwrap();
+        assert_eq!(hub.queued_weight(), 3);
+
+        drop(permit);
+        let handle = hub.handle();
+        let accounts = hub.summarize().await;
+        assert_eq!(handle.queued_weight(), 0);
+        assert_eq!(accounts[0].1.held(), Amount::ONE);
+    }
+
+    #[tokio::test]
+    async fn quiet_flag() {
+        let quiet = Arc::new(AtomicBool::new(false));
+        let hub =
+            AccountHub::new(|_client_id| InMemoryLedger::connect()).with_quiet_flag(quiet.clone());
+        let handle = hub.handle();
+        assert!(!hub.is_quiet());
+        //the flag is shared, it can be set at runtime
+        quiet.store(true, Ordering::Relaxed);
+        assert!(hub.is_quiet());
+        assert!(handle.is_quiet());
+        assert!(!AccountHub::new(|_client_id| InMemoryLedger::connect()).is_quiet());
+    }
+
+    fn transfer(id: u32, from: ClientId, to: ClientId, amount: &str) -> Action {
+        Action::Transfer {
+            id: TransactionId::from(id),
+            from,
+            to,
+            amount: Amount::from_str(amount).unwrap(),
+        }
+    }
+
+    #[tokio::test]
+    async fn transfers() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect());
+        let (client1, client2, client3) = (ClientId::from(1), ClientId::from(2), ClientId::from(3));
+        hub.execute(client1, deposit(1, "10"), &response_sender)
+            .await
+            .unwrap();
+        hub.execute(
+            client1,
+            transfer(2, client1, client2, "4"),
+            &response_sender,
+        )
+        .await
+        .unwrap();
+
+        //insufficient funds at the source
+        assert!(matches!(
+            hub.execute(
+                client1,
+                transfer(3, client1, client2, "6.0001"),
+                &response_sender
+            )
+            .await,
+            Err(ExecuteError::Transfer(TransactionError::InsufficientFunds))
+        ));
+        //a source without account
+        assert!(matches!(
+            hub.execute(
+                client3,
+                transfer(4, client3, client2, "1"),
+                &response_sender
+            )
+            .await,
+            Err(ExecuteError::Transfer(TransactionError::InsufficientFunds))
+        ));
+        //self transfer
+        assert!(matches!(
+            hub.execute(
+                client1,
+                transfer(5, client1, client1, "1"),
+                &response_sender
+            )
+            .await,
+            Err(ExecuteError::Transfer(TransactionError::SelfTransfer))
+        ));
+        //the source must be the executing client
+        assert!(matches!(
+            hub.execute(
+                client2,
+                transfer(6, client1, client2, "1"),
+                &response_sender
+            )
+            .await,
+            Err(ExecuteError::Transfer(
+                TransactionError::TransferSourceMismatch
+            ))
+        ));
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(accounts[0].1.total(), Amount::from(6));
+        assert_eq!(accounts[0].1.transaction_count(), 2);
+        assert_eq!(accounts[1].0, client2);
+        assert_eq!(accounts[1].1.total(), Amount::from(4));
+        assert_eq!(accounts[1].1.transaction_count(), 1);
+    }
+
+    #[tokio::test]
+    async fn refused_transfer_creates_no_account() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect()).with_account_policy(
+            AccountPolicy {
+                max_balance: Some(Amount::from(10)),
+                ..AccountPolicy::default()
+            },
+        );
+        let (client1, client2, client3) = (ClientId::from(1), ClientId::from(2), ClientId::from(3));
+        hub.execute(client1, deposit(1, "5"), &response_sender)
+            .await
+            .unwrap();
+        //refused by the source
+        assert!(matches!(
+            hub.execute(
+                client1,
+                transfer(2, client1, client2, "6"),
+                &response_sender
+            )
+            .await,
+            Err(ExecuteError::Transfer(TransactionError::InsufficientFunds))
+        ));
+        //refused by the new destination before the debit
+        assert!(matches!(
+            hub.execute(
+                client1,
+                transfer(4, client1, client3, "10.0001"),
+                &response_sender
+            )
+            .await,
+            Err(ExecuteError::Transfer(TransactionError::ExceedsBalanceCap))
+        ));
+        assert_eq!(hub.clients_by_first_seen(), vec![client1]);
+        hub.execute(
+            client1,
+            transfer(5, client1, client3, "5"),
+            &response_sender,
+        )
+        .await
+        .unwrap();
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts.len(), 2);
+        assert_eq!(
+            (accounts[0].0, accounts[0].1.total()),
+            (client1, Amount::ZERO)
+        );
+        assert_eq!(
+            (accounts[1].0, accounts[1].1.total()),
+            (client3, Amount::from(5))
+        );
+    }
+
+    #[tokio::test]
+    async fn transfer_to_locked_account() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect());
+        let (client1, client2) = (ClientId::from(1), ClientId::from(2));
+        hub.execute(client1, deposit(1, "10"), &response_sender)
+            .await
+            .unwrap();
+        hub.execute(client2, deposit(2, "5"), &response_sender)
+            .await
+            .unwrap();
+        for action in [
+            Action::Dispute(TransactionId::from(2)),
+            Action::ChargeBack(TransactionId::from(2)),
+        ] {
+            hub.execute(client2, action, &response_sender)
+                .await
+                .unwrap();
+        }
+        assert!(matches!(
+            hub.execute(
+                client1,
+                transfer(3, client1, client2, "4"),
+                &response_sender
+            )
+            .await,
+            Err(ExecuteError::Transfer(TransactionError::AccountLocked))
+        ));
+
+        //neither account changed
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts[0].1.total(), Amount::from(10));
+        assert_eq!(accounts[0].1.transaction_count(), 1);
+        assert_eq!(accounts[1].1.total(), Amount::ZERO);
+        assert!(accounts[1].1.is_locked());
+    }
+
+    /// refuses every insert if broken
+    struct BrokenLedger {
+        inner: InMemoryLedger,
+        broken: bool,
+    }
+
+    #[async_trait::async_trait]
+    impl Ledger for BrokenLedger {
+        type Error = LedgerError;
+
+        async fn contains(&self, key: TransactionId) -> Result<bool, Self::Error> {
+            self.inner.contains(key).await
+        }
+
+        async fn get(&self, key: TransactionId) -> Result<Option<TransactionState>, Self::Error> {
+            self.inner.get(key).await
+        }
+
+        async fn insert(
+            &mut self,
+            key: TransactionId,
+            state: TransactionState,
+        ) -> Result<(), Self::Error> {
+            if self.broken {
+                return Err(LedgerError);
+            }
+            self.inner.insert(key, state).await
+        }
+    }
+
+    #[tokio::test]
+    async fn failed_credit_refunds_the_source() {
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let (client1, client2) = (ClientId::from(1), ClientId::from(2));
+        let mut hub = AccountHub::new(|client_id| {
+            InMemoryLedger::connect().map(|inner| BrokenLedger {
+                inner,
+                broken: client_id == ClientId::from(2),
+            })
+        });
+        hub.execute(client1, deposit(1, "10"), &response_sender)
+            .await
+            .unwrap();
+        assert!(matches!(
+            hub.execute(
+                client1,
+                transfer(2, client1, client2, "4"),
+                &response_sender
+            )
+            .await,
+            Err(ExecuteError::Transfer(TransactionError::DbError))
+        ));
+        //the debit is marked as reversed
+        let id = TransactionId::from(2);
+        let outcome = hub
+            .execute_traced(client1, Action::ReverseDeposit(id), id, &response_sender)
+            .await
+            .unwrap();
+        assert_eq!(
+            outcome.state.map(|state| state.to_string()),
+            Some("Reversed(4)".to_string())
+        );
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts[0].1.total(), Amount::from(10));
+        assert_eq!(accounts[0].1.available(), Amount::from(10));
+        assert_eq!(accounts[1].1.total(), Amount::ZERO);
     }
 }
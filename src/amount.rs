@@ -1,17 +1,19 @@
 use rust_decimal::{prelude::ToPrimitive, Decimal};
+use serde::Deserialize;
 use std::error::Error;
 use std::fmt;
 use std::fmt::Display;
 use std::str::FromStr;
 
 /// Amount is a new type which represent funds.
-/// Any arithmetics with it must be carefully thought so the usual operators are not implemented, just checked ones.
+/// Any arithmetics with it must be carefully thought, so prefer the checked operations:
+/// the usual '+' and '-' operators panic on overflow (for tests and trusted internal paths only).
 ///
 /// Amount chosen not to be 'Decimal' based on the assumption that no more than 2^63/10000-1 units expected
 /// per transaction (or even in one account balance).
 /// It is using fixed point arithmetics with 4 digits precision, on a 64bit signed integer
 /// this way faster, more memory efficient, than to work on decimals
-#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
 pub struct Amount(i64);
 
 impl Amount {
@@ -25,6 +27,16 @@ impl Amount {
     pub const ONE: Amount = Amount(Amount::FRACTION);
     pub const MINUS_ONE: Amount = Amount(-Amount::FRACTION);
 
+    /// Binary representation (for storage), restored by 'from_bytes'
+    pub fn to_bytes(self) -> [u8; 8] {
+        self.0.to_be_bytes()
+    }
+
+    /// Restores an amount from its binary representation made by 'to_bytes'
+    pub fn from_bytes(bytes: [u8; 8]) -> Amount {
+        Amount(i64::from_be_bytes(bytes))
+    }
+
     /// returns None in cases when of overflow would happen!
     pub fn checked_add(self, rhs: Amount) -> Option<Amount> {
         self.0.checked_add(rhs.0).map(Amount)
@@ -34,6 +46,56 @@ impl Amount {
     pub fn checked_sub(self, rhs: Amount) -> Option<Amount> {
         self.0.checked_sub(rhs.0).map(Amount)
     }
+
+    /// multiplies by a whole scalar, returns None in cases when of overflow would happen!
+    pub fn checked_mul(self, rhs: i64) -> Option<Amount> {
+        self.0.checked_mul(rhs).map(Amount)
+    }
+
+    /// divides by a whole scalar, the result is rounded toward zero to 4 fractional digits
+    /// (e.g. 1.2345 / 2 = 0.6172, -1.2345 / 2 = -0.6172).
+    /// A percentage can be calculated as 'amount.checked_mul(percent)?.checked_div(100)'.
+    /// Returns None on division by zero or in cases when of overflow would happen!
+    pub fn checked_div(self, divisor: i64) -> Option<Amount> {
+        self.0.checked_div(divisor).map(Amount)
+    }
+
+    /// Like 'Display', but strictly positive values get a leading '+' (e.g. "+1.5", "-1.5", "0").
+    /// Meant for human-facing reports, the machine readable output should use 'Display'.
+    pub fn to_signed_string(&self) -> String {
+        if self.0 > 0 {
+            format!("+{self}")
+        } else {
+            self.to_string()
+        }
+    }
+}
+
+/// Whole units (can not overflow)
+impl From<i32> for Amount {
+    fn from(units: i32) -> Self {
+        Amount(units as i64 * Amount::FRACTION)
+    }
+}
+
+/// Panics on overflow! Use 'checked_add' where overflow is possible.
+impl std::ops::Add for Amount {
+    type Output = Amount;
+
+    fn add(self, rhs: Amount) -> Amount {
+        self.checked_add(rhs)
+            .unwrap_or_else(|| panic!("Amount overflow: {self} + {rhs}"))
+    }
+}
+
+/// Panics on overflow! Use 'checked_sub' where overflow is possible.
+impl std::ops::Sub for Amount {
+    type Output = Amount;
+
+    fn sub(self, rhs: Amount) -> Amount {
+        self.checked_sub(rhs)
+            .unwrap_or_else(|| panic!("Amount overflow: {self} - {rhs}"))
+    }
 }
 
 impl Display for Amount {
@@ -67,6 +129,103 @@ impl Display for Amount {
     }
 }
 
+/// Streaming statistics of amounts: count, min, max and mean in constant memory,
+/// the median only if the amounts are buffered too (see 'AmountStats::buffered').
+/// The mean and the median are rounded toward zero to 4 fractional digits.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AmountStats {
+    count: u64,
+    /// in fractions (even u64::MAX amounts of i64::MAX would fit)
+    sum: i128,
+    min: Option<Amount>,
+    max: Option<Amount>,
+    /// every added amount (only if buffered)
+    amounts: Option<Vec<Amount>>,
+}
+
+impl AmountStats {
+    /// Keeps every added amount in memory, so the median can be calculated
+    pub fn buffered() -> Self {
+        AmountStats {
+            amounts: Some(Vec::new()),
+            ..AmountStats::default()
+        }
+    }
+
+    pub fn add(&mut self, amount: Amount) {
+        self.count += 1;
+        self.sum = self.sum.saturating_add(amount.0 as i128);
+        self.min = Some(self.min.map_or(amount, |min| min.min(amount)));
+        self.max = Some(self.max.map_or(amount, |max| max.max(amount)));
+        if let Some(amounts) = &mut self.amounts {
+            amounts.push(amount);
+        }
+    }
+
+    /// Adds the amounts of the other statistics
+    /// (the median remains available only if both of them are buffered)
+    pub fn merge(&mut self, other: &AmountStats) {
+        self.count += other.count;
+        self.sum = self.sum.saturating_add(other.sum);
+        self.min = self.min.into_iter().chain(other.min).min();
+        self.max = self.max.into_iter().chain(other.max).max();
+        match (&mut self.amounts, &other.amounts) {
+            (Some(amounts), Some(other)) => amounts.extend_from_slice(other),
+            _ => self.amounts = None,
+        }
+    }
+
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn min(&self) -> Option<Amount> {
+        self.min
+    }
+
+    pub fn max(&self) -> Option<Amount> {
+        self.max
+    }
+
+    /// None if there are no amounts
+    pub fn mean(&self) -> Option<Amount> {
+        //the mean is between min and max, so it fits
+        (self.count > 0).then(|| Amount((self.sum / self.count as i128) as i64))
+    }
+
+    /// The middle amount (the mean of the two middle ones for an even count),
+    /// None if there are no amounts or those are not buffered
+    pub fn median(&self) -> Option<Amount> {
+        let mut amounts = self.amounts.clone()?;
+        if amounts.is_empty() {
+            return None;
+        }
+        amounts.sort_unstable();
+        let half = amounts.len() / 2;
+        if amounts.len() % 2 == 1 {
+            Some(amounts[half])
+        } else {
+            let sum = amounts[half - 1].0 as i128 + amounts[half].0 as i128;
+            Some(Amount((sum / 2) as i64))
+        }
+    }
+}
+
+impl Display for AmountStats {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let optional = |amount: Option<Amount>| amount.map_or("-".to_string(), |a| a.to_string());
+        write!(
+            f,
+            "count: {}, min: {}, max: {}, mean: {}, median: {}",
+            self.count,
+            optional(self.min),
+            optional(self.max),
+            optional(self.mean()),
+            optional(self.median())
+        )
+    }
+}
+
 /// Signals that amount parsing from string was not successful
 #[derive(Debug, PartialEq, Eq)]
 pub struct ParseError;
@@ -79,10 +238,102 @@ impl Display for ParseError {
 
 impl Error for ParseError {}
 
+/// Separator conventions of decimal numbers
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NumberLocale {
+    /// decimal point and comma thousands separators: "1,234.56"
+    #[default]
+    EnUs,
+    /// decimal comma and point thousands separators: "1.234,56"
+    EuDe,
+}
+
+impl NumberLocale {
+    /// Removes the thousands separators and replaces the decimal separator with '.'
+    /// Returns None if the separators are misplaced (thousands separators are accepted
+    /// only in the integer part, between groups of three digits).
+    pub fn normalize(self, s: &str) -> Option<String> {
+        let (thousands, decimal) = match self {
+            NumberLocale::EnUs => (',', '.'),
+            NumberLocale::EuDe => ('.', ','),
+        };
+        let (integer, fraction) = match s.split_once(decimal) {
+            Some((integer, fraction)) => (integer, Some(fraction)),
+            None => (s, None),
+        };
+        if let Some(fraction) = fraction {
+            if fraction.contains([thousands, decimal]) {
+                return None;
+            }
+        }
+        if integer.contains(thousands) {
+            let mut groups = integer.split(thousands);
+            let first = groups.next()?;
+            let first = first.strip_prefix(['+', '-']).unwrap_or(first);
+            if first.is_empty() || first.len() > 3 || groups.any(|group| group.len() != 3) {
+                return None;
+            }
+        }
+        let mut normalized = integer.replace(thousands, "");
+        if let Some(fraction) = fraction {
+            normalized.push('.');
+            normalized.push_str(fraction);
+        }
+        Some(normalized)
+    }
+}
+
+impl Amount {
+    /// Parses an amount written with the separator conventions of the given locale
+    /// (the same precision and sign rules apply as in case of 'from_str')
+    pub fn parse_locale(s: &str, locale: NumberLocale) -> Result<Amount, ParseError> {
+        locale
+            .normalize(s)
+            .ok_or(ParseError)
+            .and_then(|normalized| Amount::from_str(&normalized))
+    }
+
+    /// Parses an amount in accounting format: a fully parenthesized value is negative ("(1.50)" is -1.5),
+    /// the value inside (or without) the parentheses is parsed by 'from_str'.
+    /// Signs inside the parentheses and unbalanced parentheses are refused.
+    pub fn parse_accounting(s: &str) -> Result<Amount, ParseError> {
+        Amount::parse_parenthesized(s, Amount::from_str)
+    }
+
+    /// Same as 'parse_accounting', but the value is parsed by 'parse_locale'
+    pub fn parse_accounting_locale(s: &str, locale: NumberLocale) -> Result<Amount, ParseError> {
+        Amount::parse_parenthesized(s, |value| Amount::parse_locale(value, locale))
+    }
+
+    fn parse_parenthesized<F>(s: &str, parse: F) -> Result<Amount, ParseError>
+    where
+        F: Fn(&str) -> Result<Amount, ParseError>,
+    {
+        match s.strip_prefix('(').and_then(|s| s.strip_suffix(')')) {
+            Some(inner) => {
+                if inner.starts_with(['+', '-']) || inner.contains(['(', ')']) {
+                    return Err(ParseError);
+                }
+                parse(inner).and_then(|amount| Amount::ZERO.checked_sub(amount).ok_or(ParseError))
+            }
+            None if s.contains(['(', ')']) => Err(ParseError),
+            None => parse(s),
+        }
+    }
+}
+
+/// Parses plain decimal numbers with at most 4 fractional digits (like "-12.5").
+/// Digit separators are not accepted: rust style underscores ("1_000.50") are refused explicitly
+/// (even though the underlying decimal parser would skip them), and thousands separators
+/// are supported only by 'Amount::parse_locale'.
 impl FromStr for Amount {
     type Err = ParseError;
 
     fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.contains('_') {
+            return Err(ParseError);
+        }
         if let Ok(decimal) = Decimal::from_str(s) {
             let n = decimal * Amount::FRACTION_DEC;
             if !n.fract().is_zero() {
@@ -95,6 +346,48 @@ impl FromStr for Amount {
     }
 }
 
+/// Serialized as the decimal string of 'Display' (lossless for every value).
+/// There is no "serde" feature: serde is a required dependency (the config files need it),
+/// so the impls are always available.
+impl serde::Serialize for Amount {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/// Deserialized from a decimal string, with the same validation as 'FromStr'
+impl<'de> serde::Deserialize<'de> for Amount {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = <std::borrow::Cow<'de, str>>::deserialize(deserializer)?;
+        amount_from_serde_str(&s)
+    }
+}
+
+fn amount_from_serde_str<E: serde::de::Error>(s: &str) -> Result<Amount, E> {
+    Amount::from_str(s).map_err(|_| E::custom(format!("invalid amount: \"{s}\"")))
+}
+
+/// Deserializes an optional amount from a decimal string (for the config files)
+pub(crate) fn deserialize_optional_amount<'de, D>(
+    deserializer: D,
+) -> Result<Option<Amount>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    use serde::Deserialize;
+    <Option<std::borrow::Cow<'de, str>>>::deserialize(deserializer)?
+        .map(|s| amount_from_serde_str(&s))
+        .transpose()
+}
+
+/// The canonical acceptance check of amount fields (the same rules as used by the csv parser):
+/// plain decimal number with optional sign, at most 4 fractional digits, in the range of 'Amount',
+/// without whitespace or digit separators.
+/// The sign is accepted here, transaction specific rules (like positive deposits) are not checked.
+pub fn validate_amount(s: &str) -> Result<Amount, ParseError> {
+    Amount::from_str(s)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -105,6 +398,19 @@ mod tests {
         assert_eq!(Amount::MAX.0, 9223372036854775807);
         assert_eq!(Amount::MIN.0, -9223372036854775808);
     }
+    #[test]
+    fn bytes() {
+        for amount in [
+            Amount::MIN,
+            Amount::MINUS_ONE,
+            Amount::ZERO,
+            Amount::ONE,
+            Amount::MAX,
+        ] {
+            assert_eq!(Amount::from_bytes(amount.to_bytes()), amount);
+        }
+    }
+
     #[test]
     fn from_string() {
         assert!(Amount::from_str("").is_err());
@@ -140,6 +446,165 @@ mod tests {
         assert_eq!(Amount::from_str("-922337203685477.5808"), Ok(Amount::MIN));
     }
 
+    #[test]
+    fn serde_round_trip() {
+        for amount in [
+            Amount::MIN,
+            Amount(i64::MIN + 1),
+            Amount::MINUS_ONE,
+            Amount(-1),
+            Amount::ZERO,
+            Amount(1),
+            Amount(12345),
+            Amount::ONE,
+            Amount(i64::MAX - 1),
+            Amount::MAX,
+        ] {
+            let json = serde_json::to_string(&amount).unwrap();
+            assert_eq!(json, format!("\"{amount}\""));
+            assert_eq!(serde_json::from_str::<Amount>(&json).unwrap(), amount);
+        }
+        assert_eq!(
+            serde_json::from_str::<Amount>("\"1.5\"").unwrap(),
+            Amount::from_str("1.5").unwrap()
+        );
+        assert_eq!(
+            serde_json::to_string(&vec![Amount::ONE, Amount::from_str("-0.5").unwrap()]).unwrap(),
+            "[\"1\",\"-0.5\"]"
+        );
+    }
+
+    #[test]
+    fn serde_errors() {
+        for json in [
+            "\"1.00001\"",
+            "\"1_000\"",
+            "\"\"",
+            "\" 1\"",
+            "\"922337203685477.5808\"",
+            "\"abc\"",
+            "1.5",
+            "null",
+            "\"1.5",
+        ] {
+            assert!(serde_json::from_str::<Amount>(json).is_err(), "{json}");
+        }
+    }
+
+    #[test]
+    fn accounting_format() {
+        assert_eq!(Amount::parse_accounting("(1.50)"), Amount::from_str("-1.5"));
+        assert_eq!(Amount::parse_accounting("(0)"), Ok(Amount::ZERO));
+        assert_eq!(Amount::parse_accounting("1.50"), Amount::from_str("1.5"));
+        assert_eq!(Amount::parse_accounting("-1.50"), Amount::from_str("-1.5"));
+        assert_eq!(
+            Amount::parse_accounting("(922337203685477.5807)"),
+            Ok(Amount(-i64::MAX))
+        );
+        for malformed in [
+            "(1.50",
+            "1.50)",
+            ")1.50(",
+            "((1.50))",
+            "(-1.50)",
+            "(+1.50)",
+            "()",
+            "(1.00001)",
+            "( 1.5)",
+        ] {
+            assert_eq!(
+                Amount::parse_accounting(malformed),
+                Err(ParseError),
+                "{malformed}"
+            );
+        }
+        assert_eq!(
+            Amount::parse_accounting_locale("(1.234,5)", NumberLocale::EuDe),
+            Amount::from_str("-1234.5")
+        );
+        assert_eq!(
+            Amount::parse_accounting_locale("(1,234.5)", NumberLocale::EnUs),
+            Amount::from_str("-1234.5")
+        );
+    }
+
+    #[test]
+    fn validation() {
+        assert_eq!(validate_amount(""), Err(ParseError));
+        assert_eq!(validate_amount("."), Err(ParseError));
+        assert_eq!(validate_amount("a.a"), Err(ParseError));
+        assert_eq!(validate_amount(" 0.0"), Err(ParseError));
+        assert_eq!(validate_amount("+ 1.0"), Err(ParseError));
+        assert_eq!(validate_amount("1.00001"), Err(ParseError));
+        assert_eq!(validate_amount("1_000"), Err(ParseError));
+        assert_eq!(validate_amount("922337203685477.5808"), Err(ParseError));
+        assert_eq!(validate_amount("0."), Ok(Amount::ZERO));
+        assert_eq!(validate_amount("+1.0"), Ok(Amount::ONE));
+        assert_eq!(validate_amount("-1.00000"), Ok(Amount::MINUS_ONE));
+        assert_eq!(validate_amount("922337203685477.5807"), Ok(Amount::MAX));
+        assert_eq!(validate_amount("-922337203685477.5808"), Ok(Amount::MIN));
+    }
+
+    #[test]
+    fn digit_separators() {
+        //underscores in the integer part
+        assert_eq!(Amount::from_str("1_000.50"), Err(ParseError));
+        assert_eq!(Amount::from_str("1_000"), Err(ParseError));
+        assert_eq!(Amount::from_str("_1.2"), Err(ParseError));
+        assert_eq!(Amount::from_str("1_"), Err(ParseError));
+        assert_eq!(Amount::from_str("-1_0"), Err(ParseError));
+        //underscores in the fractional part
+        assert_eq!(Amount::from_str("1.2_5"), Err(ParseError));
+        assert_eq!(Amount::from_str("1._25"), Err(ParseError));
+        assert_eq!(Amount::from_str("1.25_"), Err(ParseError));
+        //other separators
+        assert_eq!(Amount::from_str("1,000.50"), Err(ParseError));
+        assert_eq!(Amount::from_str("1 000.50"), Err(ParseError));
+        assert_eq!(Amount::from_str("1000.50"), Ok(Amount(10005000)));
+    }
+
+    #[test]
+    fn from_locale_string() {
+        let amount = Amount::from_str("1234.56").unwrap();
+        assert_eq!(
+            Amount::parse_locale("1234.56", NumberLocale::EnUs),
+            Ok(amount)
+        );
+        assert_eq!(
+            Amount::parse_locale("1,234.56", NumberLocale::EnUs),
+            Ok(amount)
+        );
+        assert_eq!(
+            Amount::parse_locale("1.234,56", NumberLocale::EuDe),
+            Ok(amount)
+        );
+        assert_eq!(
+            Amount::parse_locale("1234,56", NumberLocale::EuDe),
+            Ok(amount)
+        );
+        assert_eq!(
+            Amount::parse_locale("-1.234.567,5", NumberLocale::EuDe),
+            Amount::from_str("-1234567.5")
+        );
+        assert_eq!(
+            Amount::parse_locale("+1,234,567", NumberLocale::EnUs),
+            Amount::from_str("1234567")
+        );
+        assert_eq!(
+            Amount::parse_locale(",5", NumberLocale::EuDe),
+            Amount::from_str(".5")
+        );
+
+        assert!(Amount::parse_locale("1.234,56", NumberLocale::EnUs).is_err());
+        assert!(Amount::parse_locale("1,234.56", NumberLocale::EuDe).is_err());
+        assert!(Amount::parse_locale("1.5", NumberLocale::EuDe).is_err());
+        assert!(Amount::parse_locale("12,34", NumberLocale::EnUs).is_err());
+        assert!(Amount::parse_locale("1234,567.8", NumberLocale::EnUs).is_err());
+        assert!(Amount::parse_locale(",123.4", NumberLocale::EnUs).is_err());
+        assert!(Amount::parse_locale("1.2,3,4", NumberLocale::EuDe).is_err());
+        assert!(Amount::parse_locale("1,00001", NumberLocale::EuDe).is_err());
+    }
+
     #[test]
     fn display() {
         assert_eq!(
@@ -262,6 +727,130 @@ mod tests {
         ); //overflow
     }
 
+    #[test]
+    fn multiplying() {
+        let amount = Amount::from_str("1.2345").unwrap();
+        assert_eq!(amount.checked_mul(3), Amount::from_str("3.7035").ok());
+        assert_eq!(amount.checked_mul(-2), Amount::from_str("-2.469").ok());
+        assert_eq!(amount.checked_mul(0), Some(Amount::ZERO));
+        assert_eq!(Amount::MAX.checked_mul(1), Some(Amount::MAX));
+        assert_eq!(Amount::MAX.checked_mul(2), None);
+        assert_eq!(Amount::MAX.checked_mul(-1), Some(Amount(-i64::MAX)));
+        assert_eq!(Amount::MIN.checked_mul(-1), None);
+        assert_eq!(
+            Amount(i64::MAX / 2).checked_mul(2),
+            Some(Amount(i64::MAX - 1))
+        );
+        assert_eq!(Amount(i64::MAX / 2 + 1).checked_mul(2), None);
+    }
+
+    #[test]
+    fn amount_stats() {
+        let amount = |s| Amount::from_str(s).unwrap();
+        let empty = AmountStats::buffered();
+        assert_eq!(empty.count(), 0);
+        assert_eq!((empty.min(), empty.max()), (None, None));
+        assert_eq!((empty.mean(), empty.median()), (None, None));
+
+        let mut stats = AmountStats::buffered();
+        for a in ["3", "1.5", "-0.5", "10"] {
+            stats.add(amount(a));
+        }
+        assert_eq!(stats.count(), 4);
+        assert_eq!(stats.min(), Some(amount("-0.5")));
+        assert_eq!(stats.max(), Some(amount("10")));
+        assert_eq!(stats.mean(), Some(amount("3.5")));
+        assert_eq!(stats.median(), Some(amount("2.25")));
+
+        let mut other = AmountStats::buffered();
+        other.add(amount("0.0001"));
+        stats.merge(&other);
+        assert_eq!(stats.count(), 5);
+        assert_eq!(stats.min(), Some(amount("-0.5")));
+        //14.0001 / 5 rounded toward zero
+        assert_eq!(stats.mean(), Some(amount("2.8")));
+        assert_eq!(stats.median(), Some(amount("1.5")));
+        assert_eq!(
+            stats.to_string(),
+            "count: 5, min: -0.5, max: 10, mean: 2.8, median: 1.5"
+        );
+
+        //the median needs every amount
+        let mut streaming = AmountStats::default();
+        streaming.add(Amount::MAX);
+        streaming.add(Amount::MAX);
+        assert_eq!(streaming.mean(), Some(Amount::MAX));
+        assert_eq!(streaming.median(), None);
+        stats.merge(&streaming);
+        assert_eq!(stats.count(), 7);
+        assert_eq!(stats.max(), Some(Amount::MAX));
+        assert_eq!(stats.median(), None);
+        assert_eq!(
+            AmountStats::default().to_string(),
+            "count: 0, min: -, max: -, mean: -, median: -"
+        );
+    }
+
+    #[test]
+    fn signed_strings() {
+        assert_eq!(Amount::from_str("1.50").unwrap().to_signed_string(), "+1.5");
+        assert_eq!(Amount(1).to_signed_string(), "+0.0001");
+        assert_eq!(Amount::MAX.to_signed_string(), "+922337203685477.5807");
+        assert_eq!(
+            Amount::from_str("-1.50").unwrap().to_signed_string(),
+            "-1.5"
+        );
+        assert_eq!(Amount(-1).to_signed_string(), "-0.0001");
+        assert_eq!(Amount::MIN.to_signed_string(), "-922337203685477.5808");
+        assert_eq!(Amount::ZERO.to_signed_string(), "0");
+    }
+
+    #[test]
+    fn dividing() {
+        let amount = Amount::from_str("1.2345").unwrap();
+        assert_eq!(amount.checked_div(2), Amount::from_str("0.6172").ok());
+        assert_eq!(
+            Amount::from_str("-1.2345").unwrap().checked_div(2),
+            Amount::from_str("-0.6172").ok()
+        );
+        assert_eq!(amount.checked_div(-1), Amount::from_str("-1.2345").ok());
+        assert_eq!(
+            Amount::from_str("0.0001").unwrap().checked_div(2),
+            Some(Amount::ZERO)
+        );
+        assert_eq!(amount.checked_div(0), None);
+        assert_eq!(Amount::ZERO.checked_div(0), None);
+        assert_eq!(Amount::MAX.checked_div(1), Some(Amount::MAX));
+        assert_eq!(Amount::MIN.checked_div(-1), None);
+
+        //15% fee
+        let fee = Amount::from_str("200.10")
+            .unwrap()
+            .checked_mul(15)
+            .and_then(|amount| amount.checked_div(100));
+        assert_eq!(fee, Amount::from_str("30.015").ok());
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(Amount::ONE + Amount::ONE, Amount::from(2));
+        assert_eq!(Amount::from(-3), Amount::from_str("-3").unwrap());
+        assert_eq!(Amount::ONE - Amount::from(2), Amount::MINUS_ONE);
+        assert_eq!(Amount::MIN - Amount::MINUS_ONE, Amount(i64::MIN + 10000));
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount overflow")]
+    fn adding_overflow() {
+        let _ = Amount::MAX + Amount::ONE;
+    }
+
+    #[test]
+    #[should_panic(expected = "Amount overflow")]
+    fn subtracting_overflow() {
+        let _ = Amount::MIN - Amount::ONE;
+    }
+
     #[test]
     fn subtracting() {
         assert_eq!(
@@ -320,6 +909,7 @@ mod tests {
     }
 
     #[test]
+    #[allow(clippy::bool_assert_comparison)]
     fn compare() {
         assert_eq!(
             Amount::from_str("0").unwrap() > Amount::from_str("0").unwrap(),
@@ -0,0 +1,217 @@
+/// Configuration of a run, loaded from a TOML or JSON file.
+/// Every section and field is optional, the missing ones take their default values.
+use serde::Deserialize;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+
+use crate::{AccountHub, AccountPolicy, CsvOptions, Ledger, LimitResponse};
+
+/// Storage of the transaction ledgers
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(tag = "backend", rename_all = "snake_case", deny_unknown_fields)]
+pub enum LedgerBackend {
+    /// Volatile, in memory ledgers
+    #[default]
+    InMemory,
+    /// Persistent ledgers in a sled database at the given path (one tree per client)
+    /// (available only if compiled with the "sled" feature)
+    Sled { path: PathBuf },
+}
+
+/// Anti-abuse limits of the AccountHub
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// Maximum number of actions a single client may have
+    pub transactions_per_client: Option<u64>,
+    /// What happens when a client exceeds the above limit
+    pub limit_response: LimitResponse,
+    /// Maximum number of account tasks processing actions concurrently (zero means unlimited)
+    pub task_permits: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RunConfig {
+    pub ledger: LedgerBackend,
+    /// Input and output format options
+    pub output: CsvOptions,
+    /// Behaviour of the accounts
+    pub policy: AccountPolicy,
+    pub limits: LimitsConfig,
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    /// the config file could not be read
+    Io(std::io::Error),
+    Toml(toml::de::Error),
+    Json(serde_json::Error),
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigError::Io(err) => write!(f, "config file error: {err}"),
+            ConfigError::Toml(err) => write!(f, "config TOML error: {err}"),
+            ConfigError::Json(err) => write!(f, "config JSON error: {err}"),
+        }
+    }
+}
+
+impl Error for ConfigError {}
+
+impl RunConfig {
+    pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+        toml::from_str(s).map_err(ConfigError::Toml)
+    }
+
+    pub fn from_json(s: &str) -> Result<Self, ConfigError> {
+        serde_json::from_str(s).map_err(ConfigError::Json)
+    }
+
+    /// Loads the config file, its format is chosen by the extension (".json", otherwise TOML)
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, ConfigError> {
+        let path = path.as_ref();
+        let content = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("json") => Self::from_json(&content),
+            _ => Self::from_toml(&content),
+        }
+    }
+
+    /// Applies the account policy and the limits to the hub
+    pub fn configure<L>(&self, accounts: AccountHub<L>) -> AccountHub<L>
+    where
+        L: Ledger + 'static,
+    {
+        let mut accounts = accounts.with_account_policy(self.policy);
+        if let Some(permits) = self.limits.task_permits.filter(|&permits| permits > 0) {
+            accounts = accounts.with_task_permits(permits);
+        }
+        match self.limits.transactions_per_client {
+            Some(limit) => accounts.with_transaction_limit(limit, self.limits.limit_response),
+            None => accounts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::in_memory_ledger::InMemoryLedger;
+    use crate::*;
+    use std::collections::BTreeMap;
+    use tokio::sync::mpsc;
+
+    const TOML: &str = r#"
+[ledger]
+backend = "sled"
+path = "/var/lib/accounter"
+
+[output]
+locale = "eu_de"
+transaction_count = true
+summary_order = "by_held_desc"
+
+[output.aliases]
+credit = "deposit"
+reverse = "chargeback"
+
+[policy]
+pending_withdrawals = true
+duplicate_dispute = "idempotent"
+
+[limits]
+transactions_per_client = 2
+limit_response = "abort_run"
+task_permits = 4
+"#;
+
+    #[test]
+    fn deserialize() {
+        let config = RunConfig::from_toml(TOML).unwrap();
+        assert_eq!(
+            config,
+            RunConfig {
+                ledger: LedgerBackend::Sled {
+                    path: PathBuf::from("/var/lib/accounter")
+                },
+                output: CsvOptions {
+                    locale: NumberLocale::EuDe,
+                    transaction_count: true,
+                    summary_order: SummaryOrder::ByHeldDesc,
+                    aliases: BTreeMap::from([
+                        ("credit".to_string(), ActionKind::Deposit),
+                        ("reverse".to_string(), ActionKind::Chargeback),
+                    ]),
+                    ..CsvOptions::default()
+                },
+                policy: AccountPolicy {
+                    pending_withdrawals: true,
+                    duplicate_dispute: DuplicateDisputePolicy::Idempotent,
+                    ..AccountPolicy::default()
+                },
+                limits: LimitsConfig {
+                    transactions_per_client: Some(2),
+                    limit_response: LimitResponse::AbortRun,
+                    task_permits: Some(4),
+                },
+            }
+        );
+
+        let config = RunConfig::from_toml("[policy]\nmax_balance = \"1000.5\"").unwrap();
+        assert_eq!(config.policy.max_balance, Amount::from_str("1000.5").ok());
+        assert!(RunConfig::from_toml("[policy]\nmax_balance = \"1.00001\"").is_err());
+
+        let json = r#"{"output": {"negative_balance_alerts": true}, "limits": {"transactions_per_client": 5}}"#;
+        let config = RunConfig::from_json(json).unwrap();
+        assert_eq!(config.ledger, LedgerBackend::InMemory);
+        assert!(config.output.negative_balance_alerts);
+        assert_eq!(config.policy, AccountPolicy::default());
+        assert_eq!(config.limits.limit_response, LimitResponse::RefuseClient);
+
+        assert_eq!(RunConfig::from_toml("").unwrap(), RunConfig::default());
+        assert!(RunConfig::from_toml("[output.aliases]\ncredit = \"refund\"").is_err());
+        assert!(matches!(
+            RunConfig::from_toml("[output]\nunknown = 1"),
+            Err(ConfigError::Toml(_))
+        ));
+        assert!(matches!(
+            RunConfig::from_json("{\"ledger\": {\"backend\": \"cloud\"}}"),
+            Err(ConfigError::Json(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn configured_hub() {
+        let config = RunConfig::from_toml(TOML).unwrap();
+        let (response_sender, _response_receiver) = mpsc::channel::<Response>(64);
+        let mut hub = config.configure(AccountHub::new(|_client_id| InMemoryLedger::connect()));
+        let client = ClientId::from(1);
+        let deposit = |id: u32| {
+            Action::Transact(
+                (TransactionId::from(id), Transaction::Deposit(Amount::ONE)),
+                None,
+            )
+        };
+        assert!(hub
+            .execute(client, deposit(1), &response_sender)
+            .await
+            .is_ok());
+        assert!(hub
+            .execute(client, deposit(2), &response_sender)
+            .await
+            .is_ok());
+        assert!(hub
+            .execute(client, deposit(3), &response_sender)
+            .await
+            .is_err());
+        assert!(hub.is_aborted());
+
+        let accounts = hub.summarize().await;
+        assert_eq!(accounts[0].1.total(), Amount::from(2));
+    }
+}
@@ -7,6 +7,7 @@ use std::fmt::Display;
 use tokio::time::{sleep, Duration};
 
 use crate::ledger::*;
+use crate::ClientId;
 
 #[derive(Debug, PartialEq, Eq)]
 pub struct LedgerError;
@@ -26,15 +27,30 @@ impl Error for LedgerError {}
 #[derive(Debug)]
 pub struct InMemoryLedger {
     db: HashMap<TransactionId, TransactionState>,
+    /// simulated duration of every database operation
+    #[cfg(feature = "simulate-delays")]
+    delay: Duration,
 }
 
 impl InMemoryLedger {
+    /// The simulated duration of the database operations of 'connect'
+    #[cfg(feature = "simulate-delays")]
+    pub const DEFAULT_DELAY: Duration = Duration::from_millis(1000);
+
     /// simulate a db connection
     pub fn connect() -> Option<Self> {
         Some(Self {
             db: HashMap::<TransactionId, TransactionState>::new(),
+            #[cfg(feature = "simulate-delays")]
+            delay: Self::DEFAULT_DELAY,
         })
     }
+
+    /// simulate a db connection, with the given duration of every database operation
+    #[cfg(feature = "simulate-delays")]
+    pub fn connect_with_delay(delay: Duration) -> Option<Self> {
+        Self::connect().map(|ledger| Self { delay, ..ledger })
+    }
 }
 
 #[async_trait]
@@ -43,7 +59,7 @@ impl Ledger for InMemoryLedger {
 
     async fn contains(&self, key: TransactionId) -> Result<bool, Self::Error> {
         #[cfg(feature = "simulate-delays")]
-        sleep(Duration::from_millis(1000)).await;
+        sleep(self.delay).await;
 
         //real db could return Err<DbError>
         Ok(self.db.contains_key(&key))
@@ -51,7 +67,7 @@ impl Ledger for InMemoryLedger {
 
     async fn get(&self, key: TransactionId) -> Result<Option<TransactionState>, Self::Error> {
         #[cfg(feature = "simulate-delays")]
-        sleep(Duration::from_millis(1000)).await;
+        sleep(self.delay).await;
 
         //real db could return Err<DbError>
         Ok(self.db.get(&key).copied())
@@ -59,16 +75,50 @@ impl Ledger for InMemoryLedger {
 
     /// must always check if returned with success!
     /// (a real db could return Err<DbError>)
-    #[must_use]
     async fn insert(
         &mut self,
         key: TransactionId,
         state: TransactionState,
     ) -> Result<(), Self::Error> {
         #[cfg(feature = "simulate-delays")]
-        sleep(Duration::from_millis(1000)).await;
+        sleep(self.delay).await;
 
         self.db.insert(key, state);
         Ok(())
     }
+
+    async fn remove(&mut self, key: TransactionId) -> Result<bool, Self::Error> {
+        #[cfg(feature = "simulate-delays")]
+        sleep(self.delay).await;
+
+        self.db.remove(&key);
+        Ok(true)
+    }
+
+    async fn clear(&mut self) -> Result<bool, Self::Error> {
+        #[cfg(feature = "simulate-delays")]
+        sleep(self.delay).await;
+
+        self.db.clear();
+        Ok(true)
+    }
+
+    async fn clear_owned(&mut self, owner: ClientId) -> Result<bool, Self::Error> {
+        #[cfg(feature = "simulate-delays")]
+        sleep(self.delay).await;
+
+        self.db
+            .retain(|_key, state| state.info().owner != Some(owner));
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn conformance() {
+        crate::ledger::conformance::run(&mut InMemoryLedger::connect().unwrap()).await;
+    }
 }
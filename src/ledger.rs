@@ -1,11 +1,13 @@
 use async_trait::async_trait;
+use serde::Deserialize;
 use std::fmt::Display;
 use std::str::FromStr;
 
 pub use crate::amount::*;
+use crate::ClientId;
 
 /// Transaction ids wrapped in new type to avoid mixing them with other ids
-#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord)]
+#[derive(Debug, PartialEq, Eq, Hash, Clone, Copy, PartialOrd, Ord, Deserialize)]
 pub struct TransactionId(u32);
 
 impl From<u32> for TransactionId {
@@ -20,6 +22,21 @@ impl Display for TransactionId {
     }
 }
 
+impl TransactionId {
+    pub const MIN: TransactionId = TransactionId(u32::MIN);
+    pub const MAX: TransactionId = TransactionId(u32::MAX);
+
+    /// The following transaction id (None after 'MAX')
+    pub fn checked_next(self) -> Option<TransactionId> {
+        self.0.checked_add(1).map(TransactionId)
+    }
+
+    /// Binary representation (for storage keys)
+    pub fn to_bytes(self) -> [u8; 4] {
+        self.0.to_be_bytes()
+    }
+}
+
 impl FromStr for TransactionId {
     type Err = std::num::ParseIntError;
 
@@ -28,15 +45,250 @@ impl FromStr for TransactionId {
     }
 }
 
-#[derive(Debug, Clone, Copy, PartialEq)]
+/// External reference (idempotency key) of a transaction, as given in the input.
+/// Actions carry it as a string, the ledger state stores it inline (at most 'Reference::MAX_LEN'
+/// bytes of UTF-8), so the states stay 'Copy'.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Reference {
+    len: u8,
+    bytes: [u8; Reference::MAX_LEN],
+}
+
+impl Reference {
+    pub const MAX_LEN: usize = 64;
+
+    /// None if the reference is empty or longer than 'MAX_LEN' bytes
+    pub fn new(s: &str) -> Option<Self> {
+        if s.is_empty() || s.len() > Reference::MAX_LEN {
+            return None;
+        }
+        let mut bytes = [0; Reference::MAX_LEN];
+        bytes[..s.len()].copy_from_slice(s.as_bytes());
+        Some(Reference {
+            len: s.len() as u8,
+            bytes,
+        })
+    }
+
+    pub fn as_str(&self) -> &str {
+        //constructed only from a valid str
+        std::str::from_utf8(&self.bytes[..self.len as usize]).unwrap_or_default()
+    }
+
+    /// Binary representation for storage, restored by 'from_bytes'
+    pub fn to_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len as usize]
+    }
+
+    /// Restores the reference from the binary representation made by 'to_bytes'
+    /// (None if the bytes are not a valid representation)
+    pub fn from_bytes(bytes: &[u8]) -> Option<Self> {
+        std::str::from_utf8(bytes).ok().and_then(Reference::new)
+    }
+}
+
+impl Display for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl std::fmt::Debug for Reference {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Reference({:?})", self.as_str())
+    }
+}
+
+/// Bookkeeping of a transaction, stored together with its state (in the same ledger entry)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TransactionInfo {
+    /// The funds currently held due to the transaction (by the open dispute of a deposit)
+    pub held: Amount,
+    /// The sequence number of a deposit ('Account::transaction_count' when it was executed),
+    /// None if it is unknown
+    pub sequence: Option<u64>,
+    /// The external reference of the transaction, if it was given in the input
+    pub reference: Option<Reference>,
+    /// The client of the account which created the transaction, None if it is unknown
+    /// (see 'Account::with_owner')
+    pub owner: Option<ClientId>,
+}
+
+impl TransactionInfo {
+    /// Appends the binary representation to the bytes, restored by 'from_bytes'
+    fn write_bytes(&self, bytes: &mut Vec<u8>) {
+        bytes.extend_from_slice(&self.held.to_bytes());
+        match self.sequence {
+            Some(sequence) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&sequence.to_be_bytes());
+            }
+            None => bytes.push(0),
+        }
+        match self.owner {
+            Some(owner) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&owner.to_bytes());
+            }
+            None => bytes.push(0),
+        }
+        //the length of the reference (0 if there is none) followed by its bytes
+        let reference = self.reference.as_ref().map_or(&[][..], Reference::to_bytes);
+        bytes.push(reference.len() as u8);
+        bytes.extend_from_slice(reference);
+    }
+
+    /// Restores the bookkeeping from the binary representation made by 'write_bytes'
+    /// (None if the bytes are not exactly a valid representation)
+    fn from_bytes(bytes: &[u8]) -> Option<TransactionInfo> {
+        let (held, rest) = split_amount(bytes)?;
+        let (sequence, rest) = match rest.split_first()? {
+            (0, rest) => (None, rest),
+            (1, rest) => {
+                let (sequence, rest) = rest.split_at_checked(8)?;
+                (Some(u64::from_be_bytes(sequence.try_into().ok()?)), rest)
+            }
+            _ => return None,
+        };
+        let (owner, rest) = match rest.split_first()? {
+            (0, rest) => (None, rest),
+            (1, rest) => {
+                let (owner, rest) = rest.split_at_checked(2)?;
+                let owner = u16::from_be_bytes(owner.try_into().ok()?);
+                (Some(ClientId::from(owner)), rest)
+            }
+            _ => return None,
+        };
+        let (&len, rest) = rest.split_first()?;
+        let reference = match (len, rest.len() == len as usize) {
+            (0, true) => None,
+            (_, true) => Some(Reference::from_bytes(rest)?),
+            (_, false) => return None,
+        };
+        Some(TransactionInfo {
+            held,
+            sequence,
+            reference,
+            owner,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TransactionState {
-    Deposit(Amount),
-    DepositInDispute(Amount),
-    ChargedBack(Amount),
+    Deposit(Amount, TransactionInfo),
+    DepositInDispute(Amount, TransactionInfo),
+    ChargedBack(Amount, TransactionInfo),
     //InDisputeWithdrawal(Amount),  //TODO ASK! - I assumed that there is no such thing as withdrawal dispute.
-    Withdrawal(Amount), //TODO ASK! this could be omitted theoretically if Withdrawal disputes are not possible,
-                        //          but in that case state restore from persisted ledger database (by transaction replay)
-                        //          would not be possible, so I leave this here...
+    Withdrawal(Amount, TransactionInfo), //TODO ASK! this could be omitted theoretically if Withdrawal disputes are not possible,
+    //          but in that case state restore from persisted ledger database (by transaction replay)
+    //          would not be possible, so I leave this here...
+    /// Withdrawal which exceeded the available funds, waiting for further deposits to be executed
+    PendingWithdrawal(Amount, TransactionInfo),
+    /// Deposit which was posted in error and reversed (without dispute),
+    /// or the debit of a transfer which was refunded since its credit failed
+    Reversed(Amount, TransactionInfo),
+}
+
+/// The name of the state with the amount, e.g. "DepositInDispute(1.5)"
+impl Display for TransactionState {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let name = match self {
+            TransactionState::Deposit(..) => "Deposit",
+            TransactionState::DepositInDispute(..) => "DepositInDispute",
+            TransactionState::ChargedBack(..) => "ChargedBack",
+            TransactionState::Withdrawal(..) => "Withdrawal",
+            TransactionState::PendingWithdrawal(..) => "PendingWithdrawal",
+            TransactionState::Reversed(..) => "Reversed",
+        };
+        write!(f, "{name}({})", self.amount())
+    }
+}
+
+impl TransactionState {
+    /// The amount of the transaction, regardless of its state
+    pub fn amount(&self) -> Amount {
+        match *self {
+            TransactionState::Deposit(amount, _)
+            | TransactionState::DepositInDispute(amount, _)
+            | TransactionState::ChargedBack(amount, _)
+            | TransactionState::Withdrawal(amount, _)
+            | TransactionState::PendingWithdrawal(amount, _)
+            | TransactionState::Reversed(amount, _) => amount,
+        }
+    }
+
+    /// The bookkeeping of the transaction, regardless of its state
+    pub fn info(&self) -> TransactionInfo {
+        match *self {
+            TransactionState::Deposit(_, info)
+            | TransactionState::DepositInDispute(_, info)
+            | TransactionState::ChargedBack(_, info)
+            | TransactionState::Withdrawal(_, info)
+            | TransactionState::PendingWithdrawal(_, info)
+            | TransactionState::Reversed(_, info) => info,
+        }
+    }
+
+    /// Whether the transaction has an open dispute
+    pub fn is_disputed(&self) -> bool {
+        matches!(self, TransactionState::DepositInDispute(..))
+    }
+
+    /// Whether the transaction was reversed by a charge back
+    pub fn is_charged_back(&self) -> bool {
+        matches!(self, TransactionState::ChargedBack(..))
+    }
+
+    /// Binary representation for storage, restored by 'from_bytes':
+    /// a tag byte, the amount, then the bookkeeping (see 'TransactionInfo')
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let tag: u8 = match self {
+            TransactionState::Deposit(..) => 0,
+            TransactionState::DepositInDispute(..) => 1,
+            TransactionState::ChargedBack(..) => 2,
+            TransactionState::Withdrawal(..) => 3,
+            TransactionState::PendingWithdrawal(..) => 4,
+            TransactionState::Reversed(..) => 5,
+        };
+        let mut bytes = Vec::with_capacity(30);
+        bytes.push(tag);
+        bytes.extend_from_slice(&self.amount().to_bytes());
+        self.info().write_bytes(&mut bytes);
+        bytes
+    }
+
+    /// Restores the state from the binary representation made by 'to_bytes'
+    /// (None if the bytes are not a valid representation).
+    /// The 9 bytes long representation of the earlier versions (without bookkeeping) is accepted too.
+    pub fn from_bytes(bytes: &[u8]) -> Option<TransactionState> {
+        let (&tag, rest) = bytes.split_first()?;
+        let (amount, rest) = split_amount(rest)?;
+        let info = match rest.is_empty() {
+            //an open dispute of the earlier versions held the whole deposit
+            true if tag == 1 => TransactionInfo {
+                held: amount,
+                ..TransactionInfo::default()
+            },
+            true => TransactionInfo::default(),
+            false => TransactionInfo::from_bytes(rest)?,
+        };
+        match tag {
+            0 => Some(TransactionState::Deposit(amount, info)),
+            1 => Some(TransactionState::DepositInDispute(amount, info)),
+            2 => Some(TransactionState::ChargedBack(amount, info)),
+            3 => Some(TransactionState::Withdrawal(amount, info)),
+            4 => Some(TransactionState::PendingWithdrawal(amount, info)),
+            5 => Some(TransactionState::Reversed(amount, info)),
+            _ => None,
+        }
+    }
+}
+
+/// Splits a binary amount (see 'Amount::to_bytes') from the beginning of the bytes
+fn split_amount(bytes: &[u8]) -> Option<(Amount, &[u8])> {
+    let (amount, rest) = bytes.split_at_checked(8)?;
+    Some((Amount::from_bytes(amount.try_into().ok()?), rest))
 }
 
 //transaction ledger trait
@@ -59,4 +311,496 @@ pub trait Ledger: Send + Sync {
         key: TransactionId,
         state: TransactionState,
     ) -> Result<(), Self::Error>;
+
+    /// removes the value belongs to the given key from the storage (or error)
+    /// returns false if the storage does not support removal (the default)
+    async fn remove(&mut self, _key: TransactionId) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// removes every value from the storage (or error)
+    /// returns false if the storage does not support removal (the default)
+    async fn clear(&mut self) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// removes the values created by the account of the given client (see 'TransactionInfo::owner')
+    /// from a storage shared by several accounts (or error)
+    /// returns false if the storage does not support it (the default)
+    async fn clear_owned(&mut self, _owner: ClientId) -> Result<bool, Self::Error> {
+        Ok(false)
+    }
+
+    /// async teardown of the storage connection (Drop can not be async), called once after the last
+    /// action of the account. It takes '&mut self' (not 'self'), since the account still owns
+    /// the ledger for its summary, but it refuses any further access (see 'Account::close').
+    async fn close(&mut self) -> Result<(), Self::Error> {
+        Ok(())
+    }
+
+    /// starts a batch of updates, which are applied together by its 'commit'
+    /// (dropping the batch without commit rolls back all of its updates)
+    async fn begin(&mut self) -> Result<TxnGuard<'_, Self>, Self::Error>
+    where
+        Self: Sized,
+    {
+        Ok(TxnGuard::new(self))
+    }
+
+    /// applies either all or none of the updates (in the given order)
+    /// The default implementation is best-effort for storages without real transactions:
+    /// the updates are applied one by one, and on failure the already applied ones are restored
+    /// (if that is still possible).
+    async fn apply_batch(
+        &mut self,
+        updates: Vec<(TransactionId, TransactionState)>,
+    ) -> Result<(), Self::Error> {
+        let mut applied = Vec::<(TransactionId, Option<TransactionState>)>::new();
+        for (key, state) in updates {
+            let result = match self.get(key).await {
+                Ok(previous) => self.insert(key, state).await.map(|_| previous),
+                Err(err) => Err(err),
+            };
+            match result {
+                Ok(previous) => applied.push((key, previous)),
+                Err(err) => {
+                    for (key, previous) in applied.into_iter().rev() {
+                        //errors (and storages not supporting removal) are ignored here,
+                        //the original error is returned
+                        let _ = match previous {
+                            Some(state) => self.insert(key, state).await,
+                            None => self.remove(key).await.map(|_| ()),
+                        };
+                    }
+                    return Err(err);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A batch of ledger updates started by 'Ledger::begin'.
+/// The updates are collected in memory and applied to the ledger only by 'commit',
+/// so dropping the guard without commit leaves the ledger untouched.
+/// (Drop can not be async, that is why the commit has to be explicit.)
+#[derive(Debug)]
+pub struct TxnGuard<'a, L> {
+    ledger: &'a mut L,
+    updates: Vec<(TransactionId, TransactionState)>,
+}
+
+impl<'a, L> TxnGuard<'a, L>
+where
+    L: Ledger,
+{
+    pub fn new(ledger: &'a mut L) -> Self {
+        TxnGuard {
+            ledger,
+            updates: Vec::new(),
+        }
+    }
+
+    /// returns the value for the given key, including the not yet committed updates
+    pub async fn get(&self, key: TransactionId) -> Result<Option<TransactionState>, L::Error> {
+        match self.updates.iter().rev().find(|(k, _)| *k == key) {
+            Some((_, state)) => Ok(Some(*state)),
+            None => self.ledger.get(key).await,
+        }
+    }
+
+    /// records an insert/update to be applied on commit
+    pub fn insert(&mut self, key: TransactionId, state: TransactionState) {
+        self.updates.push((key, state));
+    }
+
+    /// applies all the recorded updates to the ledger (or none of them on error)
+    pub async fn commit(self) -> Result<(), L::Error> {
+        self.ledger.apply_batch(self.updates).await
+    }
+}
+
+/// Generic test suite every Ledger implementation should pass
+/// (expects an empty ledger)
+#[cfg(test)]
+pub(crate) mod conformance {
+    use super::*;
+
+    pub(crate) async fn run<L: Ledger>(ledger: &mut L)
+    where
+        L::Error: std::fmt::Debug,
+    {
+        let id = TransactionId::from(7);
+        let deposit = TransactionState::Deposit(Amount::ONE, TransactionInfo::default());
+        let disputed = TransactionState::DepositInDispute(
+            Amount::ONE,
+            TransactionInfo {
+                held: Amount::ONE,
+                ..TransactionInfo::default()
+            },
+        );
+
+        assert!(!ledger.contains(id).await.unwrap());
+        assert_eq!(ledger.get(id).await.unwrap(), None);
+
+        ledger.insert(id, deposit).await.unwrap();
+        assert!(ledger.contains(id).await.unwrap());
+        assert_eq!(ledger.get(id).await.unwrap(), Some(deposit));
+
+        ledger.insert(id, disputed).await.unwrap();
+        assert_eq!(ledger.get(id).await.unwrap(), Some(disputed));
+
+        let other = TransactionId::from(u32::MAX);
+        assert!(!ledger.contains(other).await.unwrap());
+        ledger
+            .insert(
+                other,
+                TransactionState::Withdrawal(Amount::MAX, TransactionInfo::default()),
+            )
+            .await
+            .unwrap();
+        assert_eq!(
+            ledger.get(other).await.unwrap(),
+            Some(TransactionState::Withdrawal(
+                Amount::MAX,
+                TransactionInfo::default()
+            ))
+        );
+        assert_eq!(ledger.get(id).await.unwrap(), Some(disputed));
+
+        assert!(ledger.remove(other).await.unwrap());
+        assert!(!ledger.contains(other).await.unwrap());
+        assert_eq!(ledger.get(id).await.unwrap(), Some(disputed));
+
+        let mut txn = ledger.begin().await.unwrap();
+        txn.insert(other, deposit);
+        txn.insert(id, deposit);
+        txn.commit().await.unwrap();
+        assert_eq!(ledger.get(other).await.unwrap(), Some(deposit));
+        assert_eq!(ledger.get(id).await.unwrap(), Some(deposit));
+
+        //the bookkeeping is stored together with the state
+        let info = TransactionInfo {
+            held: Amount::ONE,
+            sequence: Some(7),
+            reference: Reference::new("ref-7"),
+            owner: Some(ClientId::from(7)),
+        };
+        ledger
+            .insert(id, TransactionState::DepositInDispute(Amount::ONE, info))
+            .await
+            .unwrap();
+        assert_eq!(
+            ledger.get(id).await.unwrap().map(|state| state.info()),
+            Some(info)
+        );
+        assert!(ledger.remove(id).await.unwrap());
+        assert!(!ledger.contains(id).await.unwrap());
+
+        //only the values of the given owner are removed
+        let owned = TransactionState::Deposit(Amount::ONE, info);
+        ledger.insert(id, owned).await.unwrap();
+        assert!(ledger.clear_owned(ClientId::from(8)).await.unwrap());
+        assert_eq!(ledger.get(id).await.unwrap(), Some(owned));
+        assert!(ledger.clear_owned(ClientId::from(7)).await.unwrap());
+        assert!(!ledger.contains(id).await.unwrap());
+        assert_eq!(ledger.get(other).await.unwrap(), Some(deposit));
+
+        assert!(ledger.clear().await.unwrap());
+        assert!(!ledger.contains(other).await.unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::in_memory_ledger::*;
+
+    /// fails a single insert after the given number of successful ones
+    struct FailingLedger {
+        inner: InMemoryLedger,
+        inserts_before_failure: Option<usize>,
+    }
+
+    #[async_trait]
+    impl Ledger for FailingLedger {
+        type Error = LedgerError;
+
+        async fn contains(&self, key: TransactionId) -> Result<bool, Self::Error> {
+            self.inner.contains(key).await
+        }
+
+        async fn get(&self, key: TransactionId) -> Result<Option<TransactionState>, Self::Error> {
+            self.inner.get(key).await
+        }
+
+        async fn insert(
+            &mut self,
+            key: TransactionId,
+            state: TransactionState,
+        ) -> Result<(), Self::Error> {
+            match self.inserts_before_failure {
+                Some(0) => {
+                    self.inserts_before_failure = None;
+                    return Err(LedgerError);
+                }
+                Some(n) => self.inserts_before_failure = Some(n - 1),
+                None => {}
+            }
+            self.inner.insert(key, state).await
+        }
+
+        async fn remove(&mut self, key: TransactionId) -> Result<bool, Self::Error> {
+            self.inner.remove(key).await
+        }
+
+        async fn clear(&mut self) -> Result<bool, Self::Error> {
+            self.inner.clear().await
+        }
+
+        async fn clear_owned(&mut self, owner: ClientId) -> Result<bool, Self::Error> {
+            self.inner.clear_owned(owner).await
+        }
+    }
+
+    /// implements only the required methods
+    struct MinimalLedger(std::collections::HashMap<TransactionId, TransactionState>);
+
+    #[async_trait]
+    impl Ledger for MinimalLedger {
+        type Error = LedgerError;
+
+        async fn contains(&self, key: TransactionId) -> Result<bool, Self::Error> {
+            Ok(self.0.contains_key(&key))
+        }
+
+        async fn get(&self, key: TransactionId) -> Result<Option<TransactionState>, Self::Error> {
+            Ok(self.0.get(&key).copied())
+        }
+
+        async fn insert(
+            &mut self,
+            key: TransactionId,
+            state: TransactionState,
+        ) -> Result<(), Self::Error> {
+            self.0.insert(key, state);
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn default_methods() {
+        let id = TransactionId::from(1);
+        let deposit = TransactionState::Deposit(Amount::ONE, TransactionInfo::default());
+        let mut ledger = MinimalLedger(Default::default());
+
+        let mut txn = ledger.begin().await.unwrap();
+        txn.insert(id, deposit);
+        assert_eq!(txn.commit().await, Ok(()));
+        assert_eq!(ledger.get(id).await, Ok(Some(deposit)));
+
+        //removal is not supported, nothing changes
+        assert_eq!(ledger.remove(id).await, Ok(false));
+        assert_eq!(ledger.clear().await, Ok(false));
+        assert_eq!(ledger.get(id).await, Ok(Some(deposit)));
+        assert_eq!(ledger.close().await, Ok(()));
+    }
+
+    #[test]
+    fn transaction_id_next() {
+        assert_eq!(
+            TransactionId::from(41).checked_next(),
+            Some(TransactionId::from(42))
+        );
+        assert_eq!(
+            TransactionId::MIN.checked_next(),
+            Some(TransactionId::from(1))
+        );
+        assert_eq!(
+            TransactionId::from(u32::MAX - 1).checked_next(),
+            Some(TransactionId::MAX)
+        );
+        assert_eq!(TransactionId::MAX, TransactionId::from(u32::MAX));
+        assert_eq!(TransactionId::MAX.checked_next(), None);
+    }
+
+    #[test]
+    fn references() {
+        let reference = Reference::new("INV-2024/001").unwrap();
+        assert_eq!(reference.as_str(), "INV-2024/001");
+        assert_eq!(reference.to_string(), "INV-2024/001");
+        assert_eq!(Reference::from_bytes(reference.to_bytes()), Some(reference));
+        assert_eq!(Reference::new(""), None);
+        assert!(Reference::new(&"x".repeat(Reference::MAX_LEN)).is_some());
+        assert_eq!(Reference::new(&"x".repeat(Reference::MAX_LEN + 1)), None);
+        assert_eq!(Reference::from_bytes(&[0xff]), None);
+    }
+
+    #[tokio::test]
+    async fn batch_rolled_back_on_failure() {
+        let (id1, id2, id3) = (
+            TransactionId::from(1),
+            TransactionId::from(2),
+            TransactionId::from(3),
+        );
+        let deposit = TransactionState::Deposit(Amount::ONE, TransactionInfo::default());
+        let disputed = TransactionState::DepositInDispute(Amount::ONE, TransactionInfo::default());
+        let withdrawal = TransactionState::Withdrawal(Amount::ONE, TransactionInfo::default());
+        let mut ledger = FailingLedger {
+            inner: InMemoryLedger::connect().unwrap(),
+            inserts_before_failure: Some(3),
+        };
+        ledger.insert(id1, deposit).await.unwrap();
+
+        //the third insert of the batch fails
+        let mut txn = ledger.begin().await.unwrap();
+        txn.insert(id1, disputed);
+        txn.insert(id2, withdrawal);
+        assert_eq!(txn.get(id1).await, Ok(Some(disputed)));
+        txn.insert(id3, withdrawal);
+        assert_eq!(txn.commit().await, Err(LedgerError));
+
+        assert_eq!(ledger.get(id1).await, Ok(Some(deposit)));
+        assert_eq!(ledger.contains(id2).await, Ok(false));
+        assert_eq!(ledger.contains(id3).await, Ok(false));
+    }
+
+    #[tokio::test]
+    async fn batch_committed_or_dropped() {
+        let (id1, id2) = (TransactionId::from(1), TransactionId::from(2));
+        let deposit = TransactionState::Deposit(Amount::ONE, TransactionInfo::default());
+        let mut ledger = InMemoryLedger::connect().unwrap();
+
+        let mut txn = ledger.begin().await.unwrap();
+        txn.insert(id1, deposit);
+        drop(txn);
+        assert_eq!(ledger.contains(id1).await, Ok(false));
+
+        let mut txn = ledger.begin().await.unwrap();
+        txn.insert(id1, deposit);
+        txn.insert(id2, deposit);
+        assert_eq!(txn.commit().await, Ok(()));
+        assert_eq!(ledger.get(id1).await, Ok(Some(deposit)));
+        assert_eq!(ledger.get(id2).await, Ok(Some(deposit)));
+    }
+
+    #[test]
+    fn state_accessors() {
+        let amount = Amount::from_str("12.3456").unwrap();
+
+        let state = TransactionState::Deposit(amount, TransactionInfo::default());
+        assert_eq!(state.amount(), amount);
+        assert!(!state.is_disputed());
+        assert!(!state.is_charged_back());
+
+        let state = TransactionState::DepositInDispute(amount, TransactionInfo::default());
+        assert_eq!(state.amount(), amount);
+        assert!(state.is_disputed());
+        assert!(!state.is_charged_back());
+
+        let state = TransactionState::ChargedBack(amount, TransactionInfo::default());
+        assert_eq!(state.amount(), amount);
+        assert!(!state.is_disputed());
+        assert!(state.is_charged_back());
+
+        let state = TransactionState::Withdrawal(amount, TransactionInfo::default());
+        assert_eq!(state.amount(), amount);
+        assert!(!state.is_disputed());
+        assert!(!state.is_charged_back());
+
+        let state = TransactionState::PendingWithdrawal(amount, TransactionInfo::default());
+        assert_eq!(state.amount(), amount);
+        assert!(!state.is_disputed());
+        assert!(!state.is_charged_back());
+
+        let state = TransactionState::Reversed(amount, TransactionInfo::default());
+        assert_eq!(state.amount(), amount);
+        assert!(!state.is_disputed());
+        assert!(!state.is_charged_back());
+    }
+
+    #[test]
+    fn state_bytes() {
+        for amount in [Amount::MIN, Amount::ZERO, Amount::ONE, Amount::MAX] {
+            for info in [
+                TransactionInfo::default(),
+                TransactionInfo {
+                    held: amount,
+                    ..TransactionInfo::default()
+                },
+                TransactionInfo {
+                    held: amount,
+                    sequence: Some(u64::MAX),
+                    reference: Reference::new(&"x".repeat(Reference::MAX_LEN)),
+                    owner: Some(ClientId::MAX),
+                },
+            ] {
+                for state in [
+                    TransactionState::Deposit(amount, info),
+                    TransactionState::DepositInDispute(amount, info),
+                    TransactionState::ChargedBack(amount, info),
+                    TransactionState::Withdrawal(amount, info),
+                    TransactionState::PendingWithdrawal(amount, info),
+                    TransactionState::Reversed(amount, info),
+                ] {
+                    assert_eq!(TransactionState::from_bytes(&state.to_bytes()), Some(state));
+                }
+            }
+        }
+        assert_eq!(TransactionState::from_bytes(&[]), None);
+        assert_eq!(TransactionState::from_bytes(&[0; 8]), None);
+        assert_eq!(TransactionState::from_bytes(&[0; 10]), None);
+        assert_eq!(TransactionState::from_bytes(&[0; 19]), None);
+        assert_eq!(TransactionState::from_bytes(&[0; 21]), None);
+        assert_eq!(TransactionState::from_bytes(&[255; 20]), None);
+        //the sequence is either missing (0) or present (1)
+        let mut bytes = [0; 28];
+        bytes[17] = 2;
+        assert_eq!(TransactionState::from_bytes(&bytes), None);
+        bytes[17] = 1;
+        assert!(TransactionState::from_bytes(&bytes).is_some());
+        assert_eq!(TransactionState::from_bytes(&bytes[..27]), None);
+        //so is the owner
+        let mut bytes = [0; 22];
+        bytes[18] = 2;
+        assert_eq!(TransactionState::from_bytes(&bytes), None);
+        bytes[18] = 1;
+        assert!(TransactionState::from_bytes(&bytes).is_some());
+        assert_eq!(TransactionState::from_bytes(&bytes[..21]), None);
+        //the reference has exactly the given length, and it is valid UTF-8
+        let mut bytes = [0; 22];
+        bytes[19] = 2;
+        bytes[20..].copy_from_slice(b"ab");
+        assert!(TransactionState::from_bytes(&bytes).is_some());
+        assert_eq!(TransactionState::from_bytes(&bytes[..21]), None);
+        bytes[20] = 0xff;
+        assert_eq!(TransactionState::from_bytes(&bytes), None);
+    }
+
+    #[test]
+    fn legacy_state_bytes() {
+        //a tag byte and the amount, without bookkeeping
+        let mut bytes = [0; 9];
+        bytes[1..].copy_from_slice(&Amount::ONE.to_bytes());
+        assert_eq!(
+            TransactionState::from_bytes(&bytes),
+            Some(TransactionState::Deposit(
+                Amount::ONE,
+                TransactionInfo::default()
+            ))
+        );
+        //an open dispute held the whole deposit
+        bytes[0] = 1;
+        assert_eq!(
+            TransactionState::from_bytes(&bytes),
+            Some(TransactionState::DepositInDispute(
+                Amount::ONE,
+                TransactionInfo {
+                    held: Amount::ONE,
+                    ..TransactionInfo::default()
+                }
+            ))
+        );
+        assert_eq!(TransactionState::from_bytes(&[255; 9]), None);
+    }
 }
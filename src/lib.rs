@@ -1,38 +1,339 @@
 pub mod account;
 pub mod account_hub;
 pub mod amount;
+pub mod config;
 pub mod in_memory_ledger;
 pub mod ledger;
+pub mod read_only_ledger;
+#[cfg(feature = "sled")]
+pub mod sled_ledger;
+pub mod summary;
 
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::path::PathBuf;
 use std::str::FromStr;
-use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use std::sync::atomic::AtomicBool;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt};
 use tokio::sync::mpsc;
 
-use log::{error, info, warn};
+use log::{error, info, log_enabled, warn};
 use pest::Parser;
 use pest_derive::*;
+use serde::Deserialize;
 
 pub use crate::account_hub::*;
-use crate::amount::Amount;
 
 #[derive(Parser)]
 #[grammar = "actions.pest"]
 struct ActionParser;
 
+/// Field delimiter of the csv input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Delimiter {
+    #[default]
+    Comma,
+    Semicolon,
+    Tab,
+}
+
+impl Delimiter {
+    pub fn as_char(self) -> char {
+        match self {
+            Delimiter::Comma => ',',
+            Delimiter::Semicolon => ';',
+            Delimiter::Tab => '\t',
+        }
+    }
+
+    /// The grammar rule of a line separated by this delimiter
+    fn rule(self) -> Rule {
+        match self {
+            Delimiter::Comma => Rule::comma_line,
+            Delimiter::Semicolon => Rule::semicolon_line,
+            Delimiter::Tab => Rule::tab_line,
+        }
+    }
+}
+
+/// Action types of the type column (the targets of the configured aliases)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionKind {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+impl ActionKind {
+    /// The canonical keyword of the type column
+    pub fn keyword(self) -> &'static str {
+        match self {
+            ActionKind::Deposit => "deposit",
+            ActionKind::Withdrawal => "withdrawal",
+            ActionKind::Dispute => "dispute",
+            ActionKind::Resolve => "resolve",
+            ActionKind::Chargeback => "chargeback",
+        }
+    }
+}
+
+/// Statistics of the accepted deposit amounts in the report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DepositStatsMode {
+    #[default]
+    Off,
+    /// count, min, max and mean (in constant memory)
+    Streaming,
+    /// the median too (every accepted deposit amount is kept in memory until the summary)
+    Buffered,
+}
+
+/// Options of the csv processing, the defaults fit to the "type, client, tx, amount" format
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct CsvOptions {
+    /// Field delimiter of the input (the summary is always comma separated)
+    pub delimiter: Delimiter,
+    /// Separator conventions of the amount column
+    /// (amounts containing commas must be quoted, like "1,234.56", if comma is the delimiter)
+    pub locale: NumberLocale,
+    /// Amounts in parentheses are negatives, as in accounting exports (e.g. "(1.50)" is -1.5)
+    pub accounting_negatives: bool,
+    /// Synonyms accepted in the type column besides the canonical keywords (e.g. "credit" for deposit),
+    /// other words are still refused
+    pub aliases: BTreeMap<String, ActionKind>,
+    /// Adds a "transactions" column to the summary with the number of successful actions per client
+    pub transaction_count: bool,
+    /// Collects the clients having negative available or total funds (due to charge backs)
+    /// into the report and logs them as warnings.
+    pub negative_balance_alerts: bool,
+    /// Order of the accounts in the summary
+    pub summary_order: SummaryOrder,
+    /// Collects statistics of the accepted deposit amounts into the report
+    pub deposit_stats: DepositStatsMode,
+    /// Traces the actions of this transaction into the report, with their outcomes
+    /// (each of them is awaited, so the processing gets slower)
+    pub explain: Option<TransactionId>,
+    /// Number of bytes to skip at the beginning of the input (to resume an interrupted run)
+    pub start_offset: u64,
+    /// The optional fifth field of the deposits and withdrawals is their external reference
+    /// (a single word or a quoted field), stored with the transaction and shown in the explain steps.
+    /// A reference longer than 'Reference::MAX_LEN' bytes makes the line a parse error.
+    /// Otherwise (and for any other fifth field) it is the beginning of the comment.
+    pub references: bool,
+    /// Clients seen only in refused lines (e.g. malformed ones) are also included in the summary
+    /// with zeroed accounts (the client id is taken from the second field of such lines)
+    pub include_unparsed_clients: bool,
+    /// Writes the summary of each account into a separate "<client_id>.json" file
+    /// in this directory (created if missing) instead of the csv output
+    /// (a file which can not be written fails the processing with its I/O error)
+    pub json_dir: Option<PathBuf>,
+    /// Nothing is logged during the processing (by the hub and its accounts neither, see
+    /// 'AccountHub::with_quiet_flag'), regardless of the log level filter (see the --quiet flag)
+    #[serde(skip)]
+    pub quiet: bool,
+}
+
+/// Order of the accounts in the summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SummaryOrder {
+    /// Ascending client ids
+    #[default]
+    ByClientId,
+    /// Largest held (disputed) amounts first, ties by ascending client ids
+    ByHeldDesc,
+    /// In the order of the first appearance of the clients in the input
+    FirstSeen,
+}
+
+/// Throughput statistics of a run
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ProcessStats {
+    /// All the lines of the input (including header and empty lines)
+    pub lines_read: u64,
+    /// Successfully executed actions
+    pub executed: u64,
+    /// Lines which were malformed or refused before reaching an account (see 'OutcomeCounts::error'),
+    /// the actions refused by the accounts are reported in the logged responses
+    pub refused: u64,
+    /// Duration of the whole processing (including the summary writing)
+    pub elapsed: Duration,
+    /// Responses not logged because the logger fell behind (the response channel was full)
+    pub dropped_responses: u64,
+}
+
+impl ProcessStats {
+    pub fn lines_per_sec(&self) -> f64 {
+        let secs = self.elapsed.as_secs_f64();
+        if secs > 0.0 {
+            self.lines_read as f64 / secs
+        } else {
+            0.0
+        }
+    }
+}
+
+impl fmt::Display for ProcessStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "lines read: {}, executed: {}, refused: {}, elapsed: {:.3}s, lines/sec: {:.0}",
+            self.lines_read,
+            self.executed,
+            self.refused,
+            self.elapsed.as_secs_f64(),
+            self.lines_per_sec()
+        )
+    }
+}
+
+/// What happened to a line of the input
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ParseOutcome {
+    /// the action of the line was forwarded to the account of the client
+    /// (the account may still refuse it, see the logged responses)
+    Executed,
+    /// a line starting with the "type" column name
+    Header,
+    /// a line starting with "//"
+    Comment,
+    /// an empty (or whitespace only) line
+    Blank,
+    /// a malformed line, or an action refused before reaching the account (with the reason)
+    Error(String),
+}
+
+/// Number of the input lines per 'ParseOutcome'
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct OutcomeCounts {
+    pub executed: u64,
+    pub header: u64,
+    pub comment: u64,
+    pub blank: u64,
+    pub error: u64,
+}
+
+impl OutcomeCounts {
+    pub fn add(&mut self, outcome: &ParseOutcome) {
+        match outcome {
+            ParseOutcome::Executed => self.executed += 1,
+            ParseOutcome::Header => self.header += 1,
+            ParseOutcome::Comment => self.comment += 1,
+            ParseOutcome::Blank => self.blank += 1,
+            ParseOutcome::Error(_) => self.error += 1,
+        }
+    }
+}
+
+/// Results of the processing beyond the written summary
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProcessReport {
+    /// Clients with negative available or total funds (only if enabled in the options)
+    pub negative_balances: Vec<ClientId>,
+    pub stats: ProcessStats,
+    /// Byte offset in the input after the last processed line (a run can be resumed from here)
+    pub end_offset: u64,
+    /// Classification of the processed lines
+    pub outcomes: OutcomeCounts,
+    /// Statistics of the accepted deposit amounts (only if enabled in the options)
+    pub deposit_stats: Option<AmountStats>,
+    /// The actions of the explained transaction in the order of the input (only if enabled in the options)
+    pub explain: Vec<ExplainStep>,
+}
+
+/// An action of the explained transaction (see 'CsvOptions::explain')
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExplainStep {
+    /// the number of the line in the input (counted from the start offset, the first line is 1)
+    pub line: u64,
+    /// the trimmed text of the line
+    pub text: String,
+    pub client_id: ClientId,
+    pub action: Action,
+    /// the outcome of the action, or the reason why it did not reach the account
+    pub outcome: Result<TracedOutcome, String>,
+}
+
+impl fmt::Display for ExplainStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {} \"{}\": ", self.line, self.text)?;
+        match &self.outcome {
+            Ok(outcome) => {
+                match outcome.result {
+                    Ok(()) => write!(f, "ok")?,
+                    Err(err) => write!(f, "{err:?}")?,
+                }
+                match outcome.state {
+                    Some(state) => write!(f, ", tx: {state}")?,
+                    None => write!(f, ", tx: -")?,
+                }
+                if let Some(reference) = outcome.state.and_then(|state| state.info().reference) {
+                    write!(f, ", ref: {reference}")?;
+                }
+                let balances = &outcome.balances;
+                write!(
+                    f,
+                    ", available: {}, held: {}, total: {}, locked: {}",
+                    balances.available, balances.held, balances.total, balances.locked
+                )
+            }
+            Err(reason) => write!(f, "not executed ({reason})"),
+        }
+    }
+}
+
 /// tuns a csv record into executable actions
-fn parse_csv_line(line: &str) -> Result<(ClientId, Action), ParseError> {
-    if let Ok(items) = ActionParser::parse(Rule::line_input, line) {
+pub fn parse_csv_line(line: &str, options: &CsvOptions) -> Result<(ClientId, Action), ParseError> {
+    let delimiter = options.delimiter.as_char();
+    //a configured synonym in the type column is replaced by its canonical keyword
+    //(the line is copied only in that case)
+    let input = match options.aliases.is_empty() {
+        true => Cow::Borrowed(line),
+        false => {
+            let (typ, rest) = line.split_once(delimiter).unwrap_or((line, ""));
+            match options.aliases.get(typ.trim()) {
+                Some(kind) => Cow::Owned(format!("{}{delimiter}{rest}", kind.keyword())),
+                None => Cow::Borrowed(line),
+            }
+        }
+    };
+    if let Ok(items) = ActionParser::parse(options.delimiter.rule(), &input) {
         //we get here only with valid number of items thanks to the parser!
         let mut cid = Option::<ClientId>::None;
         let mut tid = Option::<TransactionId>::None;
         let mut amount = Option::<Amount>::None;
+        let mut reference = Option::<String>::None;
         let mut typ: Rule = Rule::EOI;
 
-        for item in items {
+        for item in items.flatten() {
             match item.as_rule() {
                 Rule::client_id => cid = ClientId::from_str(item.as_str()).ok(),
                 Rule::transaction_id => tid = TransactionId::from_str(item.as_str()).ok(),
-                Rule::amount => amount = Amount::from_str(item.as_str()).ok(),
+                Rule::amount => {
+                    let value = item.as_str().trim_matches('"');
+                    amount = if options.accounting_negatives {
+                        Amount::parse_accounting_locale(value, options.locale)
+                    } else {
+                        Amount::parse_locale(value, options.locale)
+                    }
+                    .ok()
+                }
+                Rule::reference if options.references => {
+                    let value = item.as_str().trim_matches('"');
+                    if value.len() > Reference::MAX_LEN {
+                        return Err(ParseError);
+                    }
+                    reference = Some(value.to_string())
+                }
                 Rule::deposit => typ = Rule::deposit,
                 Rule::withdrawal => typ = Rule::withdrawal,
                 Rule::dispute => typ = Rule::dispute,
@@ -43,13 +344,13 @@ fn parse_csv_line(line: &str) -> Result<(ClientId, Action), ParseError> {
         }
 
         if let (Some(cid), Some(tid)) = (cid, tid) {
-            match (typ, amount) {
-                (Rule::deposit, Some(amount)) => {
-                    Some(Action::Transact((tid, Transaction::Deposit(amount))))
-                }
-                (Rule::withdrawal, Some(amount)) => {
-                    Some(Action::Transact((tid, Transaction::Withdrawal(amount))))
-                }
+            let transaction = match (typ, amount) {
+                (Rule::deposit, Some(amount)) => Some(Transaction::Deposit(amount)),
+                (Rule::withdrawal, Some(amount)) => Some(Transaction::Withdrawal(amount)),
+                _ => None,
+            };
+            match (typ, transaction) {
+                (_, Some(transaction)) => Some(Action::Transact((tid, transaction), reference)),
                 (Rule::dispute, _) => Some(Action::Dispute(tid)),
                 (Rule::resolve, _) => Some(Action::Resolve(tid)),
                 (Rule::charge_back, _) => Some(Action::ChargeBack(tid)),
@@ -65,13 +366,168 @@ fn parse_csv_line(line: &str) -> Result<(ClientId, Action), ParseError> {
     }
 }
 
+/// Generates a deterministic, well formed transactions file with the given number of records
+/// (deposits, withdrawals, disputes and resolves spread over 64 clients) - for benchmarks and tests.
+/// Not part of the supported API, it is public only because the benchmarks are a separate crate.
+#[doc(hidden)]
+pub fn synthetic_csv(records: usize) -> String {
+    const CLIENTS: usize = 64;
+    let mut csv = String::from("type, client, tx, amount\n");
+    for i in 0..records {
+        let client = i % CLIENTS + 1;
+        let tx = i + 1;
+        let line = match (i / CLIENTS) % 8 {
+            0..=4 => format!(
+                "deposit, {client}, {tx}, {}.{:04}\n",
+                i % 1000 + 1,
+                i % 10000
+            ),
+            5 => format!("withdrawal, {client}, {tx}, 0.5\n"),
+            //these refer to the first deposit of the same client in the current block of 8
+            6 => format!("dispute, {client}, {}\n", tx - 6 * CLIENTS),
+            _ => format!("resolve, {client}, {}\n", tx - 7 * CLIENTS),
+        };
+        csv.push_str(&line);
+    }
+    csv
+}
+
+/// Separator of the summary fields
+const OUTPUT_DELIMITER: char = ',';
+
+/// Quotes a csv field according to RFC-4180, if it contains the delimiter, a quote or a line break:
+/// the field is wrapped in double quotes and the internal quotes are doubled.
+pub fn quote_csv_field(field: &str, delimiter: char) -> Cow<'_, str> {
+    if field.contains([delimiter, '"', '\n', '\r']) {
+        Cow::Owned(format!("\"{}\"", field.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(field)
+    }
+}
+
+/// Formats a summary line from the given fields (quoted as needed)
+fn csv_row<S: AsRef<str>>(fields: &[S]) -> String {
+    let mut row = String::new();
+    for (i, field) in fields.iter().enumerate() {
+        if i > 0 {
+            row.push(OUTPUT_DELIMITER);
+            row.push(' ');
+        }
+        row.push_str(&quote_csv_field(field.as_ref(), OUTPUT_DELIMITER));
+    }
+    row.push('\n');
+    row
+}
+
+/// A column of the summary
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Column {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+    /// Number of successful actions
+    Transactions,
+}
+
+impl Column {
+    /// the name of the column in the header
+    pub fn name(self) -> &'static str {
+        match self {
+            Column::Client => "client",
+            Column::Available => "available",
+            Column::Held => "held",
+            Column::Total => "total",
+            Column::Locked => "locked",
+            Column::Transactions => "transactions",
+        }
+    }
+
+    /// the field of the column for the given account
+    pub fn value<L: Ledger>(self, client_id: ClientId, account: &Account<L>) -> String {
+        match self {
+            Column::Client => client_id.to_string(),
+            Column::Available => account.available().to_string(),
+            Column::Held => account.held().to_string(),
+            Column::Total => account.total().to_string(),
+            Column::Locked => account.is_locked().to_string(),
+            Column::Transactions => account.transaction_count().to_string(),
+        }
+    }
+}
+
+/// The columns of the summary, this drives both the header and the rows, so they are always in sync.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnSet(Vec<Column>);
+
+impl ColumnSet {
+    /// the columns enabled by the options
+    pub fn from_options(options: &CsvOptions) -> Self {
+        let mut columns = vec![
+            Column::Client,
+            Column::Available,
+            Column::Held,
+            Column::Total,
+            Column::Locked,
+        ];
+        if options.transaction_count {
+            columns.push(Column::Transactions);
+        }
+        ColumnSet(columns)
+    }
+
+    pub fn columns(&self) -> &[Column] {
+        &self.0
+    }
+
+    /// the header line (with line ending)
+    pub fn header(&self) -> String {
+        let mut header = self
+            .0
+            .iter()
+            .map(|column| column.name())
+            .collect::<Vec<_>>()
+            .join(&OUTPUT_DELIMITER.to_string());
+        header.push('\n');
+        header
+    }
+
+    /// the summary of an account as a json object, with the column names as keys
+    /// (all the fields are valid json numbers or booleans)
+    pub fn json<L: Ledger>(&self, client_id: ClientId, account: &Account<L>) -> String {
+        let fields: Vec<_> = self
+            .0
+            .iter()
+            .map(|column| {
+                format!(
+                    "\"{}\": {}",
+                    column.name(),
+                    column.value(client_id, account)
+                )
+            })
+            .collect();
+        format!("{{{}}}\n", fields.join(", "))
+    }
+
+    /// the summary line of an account (with line ending)
+    pub fn row<L: Ledger>(&self, client_id: ClientId, account: &Account<L>) -> String {
+        let fields: Vec<_> = self
+            .0
+            .iter()
+            .map(|column| column.value(client_id, account))
+            .collect();
+        csv_row(&fields)
+    }
+}
+
 /// Processes the lines of a csv file from 'reader'.
 /// The "type, client, tx, amount" header is skipped, just like any other lines with parse error.
 /// Executes the transactions given in well formed lines, the writes out the summary of each client account in csv format with
 /// "client,available,held,total,locked" header line to 'writer'.
 /// If logging is enabled (in environment variable RUST_LOG=trace), failures are logged on stderr.
 pub async fn process_csv<R, W, L>(
-    mut accounts: AccountHub<L>,
+    accounts: AccountHub<L>,
     reader: R,
     writer: &mut W,
 ) -> Result<(), std::io::Error>
@@ -80,64 +536,352 @@ where
     W: AsyncWriteExt + Unpin + Send,
     L: Ledger + 'static,
 {
-    // spawn a task for logging action responses:
-    let (response_sender, mut response_receiver) =
-        mpsc::channel::<(Result<(), TransactionError>, (ClientId, Action))>(64);
-    tokio::spawn(async move {
-        while let Some((_response, (_client_id, _action))) = response_receiver.recv().await {
-            match _response {
-                Ok(()) => info!("Transaction successful: {_client_id} {:?}", _action),
-                Err(err) => {
-                    warn!("Transaction refused: {err} - {_client_id} {:?}", _action)
+    process_csv_with(accounts, reader, writer, &CsvOptions::default())
+        .await
+        .map(|_report| ())
+}
+
+/// Same as 'process_csv', but the processing can be customized by 'options',
+/// and further results are returned in a report.
+pub async fn process_csv_with<R, W, L>(
+    accounts: AccountHub<L>,
+    reader: R,
+    writer: &mut W,
+    options: &CsvOptions,
+) -> Result<ProcessReport, std::io::Error>
+where
+    R: AsyncBufReadExt + Unpin,
+    W: AsyncWriteExt + Unpin + Send,
+    L: Ledger + 'static,
+{
+    let mut processor = CsvProcessor::new(accounts, options);
+    processor.read(reader).await?;
+    processor.finish(writer).await
+}
+
+/// Same as 'process_csv', but takes already split lines (handy for tests).
+pub async fn process_lines<I, W, L>(
+    accounts: AccountHub<L>,
+    lines: I,
+    writer: &mut W,
+) -> Result<(), std::io::Error>
+where
+    I: IntoIterator<Item = String>,
+    W: AsyncWriteExt + Unpin + Send,
+    L: Ledger + 'static,
+{
+    let options = CsvOptions::default();
+    let mut processor = CsvProcessor::new(accounts, &options);
+    for line in lines {
+        if !processor.process_line(&line).await {
+            break;
+        }
+    }
+    processor.finish(writer).await.map(|_report| ())
+}
+
+/// Executes the actions line by line, then writes out the summary
+/// (the common part of the different input sources).
+/// The input can be fed in more parts (e.g. to resume an interrupted read of a file).
+pub struct CsvProcessor<'a, L>
+where
+    L: Ledger + 'static,
+{
+    accounts: AccountHub<L>,
+    options: &'a CsvOptions,
+    response_sender: mpsc::Sender<Response>,
+    report: ProcessReport,
+    started: Instant,
+    /// the position in the input after the last processed line
+    offset: u64,
+}
+
+impl<'a, L> CsvProcessor<'a, L>
+where
+    L: Ledger + 'static,
+{
+    /// The processing starts at the start offset given in the options
+    pub fn new(accounts: AccountHub<L>, options: &'a CsvOptions) -> Self {
+        let accounts = match options.deposit_stats {
+            DepositStatsMode::Buffered => accounts.with_buffered_deposit_amounts(),
+            DepositStatsMode::Off | DepositStatsMode::Streaming => accounts,
+        };
+        let accounts = match options.quiet {
+            true => accounts.with_quiet_flag(Arc::new(AtomicBool::new(true))),
+            false => accounts,
+        };
+        // spawn a task for logging action responses
+        // (in quiet mode, or if nothing would be logged, the receiver is dropped,
+        // so the accounts do not send their responses):
+        let (response_sender, mut response_receiver) = mpsc::channel::<Response>(64);
+        if !accounts.is_quiet() && log_enabled!(log::Level::Error) {
+            let handle = accounts.handle();
+            tokio::spawn(async move {
+                while let Some((_response, (_client_id, _action))) = response_receiver.recv().await
+                {
+                    //the hub may be silenced at runtime
+                    if handle.is_quiet() {
+                        continue;
+                    }
+                    match _response {
+                        Ok(()) => info!("Transaction successful: {_client_id} {:?}", _action),
+                        Err(err) => {
+                            warn!("Transaction refused: {err} - {_client_id} {:?}", _action)
+                        }
+                    }
+                }
+            });
+        }
+
+        Self {
+            accounts,
+            options,
+            response_sender,
+            report: ProcessReport::default(),
+            started: Instant::now(),
+            offset: options.start_offset,
+        }
+    }
+
+    /// The position in the input after the last processed line
+    pub fn offset(&self) -> u64 {
+        self.offset
+    }
+
+    /// Processes the lines of a csv input positioned to its beginning.
+    /// The bytes before the current offset are skipped (as already processed),
+    /// returns the offset reached at the end of the input (or after an abort).
+    pub async fn read<R>(&mut self, mut reader: R) -> Result<u64, std::io::Error>
+    where
+        R: AsyncBufReadExt + Unpin,
+    {
+        let skipped =
+            tokio::io::copy(&mut (&mut reader).take(self.offset), &mut tokio::io::sink()).await?;
+        if skipped < self.offset {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "the input is shorter than the start offset",
+            ));
+        }
+
+        let mut line = String::new();
+        loop {
+            line.clear();
+            match reader.read_line(&mut line).await {
+                Ok(0) | Err(_) => break,
+                Ok(length) => {
+                    self.offset += length as u64;
+                    let line = line.trim_end_matches('\n').trim_end_matches('\r');
+                    if !self.process_line(line).await {
+                        break;
+                    }
                 }
             }
         }
-    });
+        Ok(self.offset)
+    }
 
-    // read the file and process the lines
-    // a part of the possible errors returned immediately
-    // the rest is collected by the above spawned task.
-    let mut lines = reader.lines();
-    while let Ok(Some(line)) = lines.next_line().await {
-        match parse_csv_line(&line) {
+    /// Executes the action of a line, returns false if the processing should be stopped.
+    async fn process_line(&mut self, line: &str) -> bool {
+        self.report.stats.lines_read += 1;
+        let outcome = self.execute_line(line).await;
+        self.report.outcomes.add(&outcome);
+        if self.accounts.is_aborted() {
+            if let ParseOutcome::Error(_reason) = outcome {
+                if !self.accounts.is_quiet() {
+                    error!("Processing aborted: {_reason}");
+                }
+            }
+            return false;
+        }
+        true
+    }
+
+    /// Classifies the line and executes its action (if it has any).
+    /// A part of the possible errors logged immediately,
+    /// the rest is collected by the above spawned task.
+    async fn execute_line(&mut self, line: &str) -> ParseOutcome {
+        let trimmed = line.trim();
+        if trimmed.is_empty() {
+            return ParseOutcome::Blank;
+        }
+        if trimmed.starts_with("//") {
+            return ParseOutcome::Comment;
+        }
+        let delimiter = self.options.delimiter.as_char();
+        match parse_csv_line(line, self.options) {
             Ok((client_id, action)) => {
-                if let Err(_err) = accounts.execute(client_id, action, &response_sender).await {
-                    warn!(
-                        "Transaction refused: {_err} (client: {client_id} {:?})",
-                        action
-                    );
+                let result = match self.options.explain {
+                    Some(traced) if action.transaction_id() == Some(traced) => {
+                        let result = self
+                            .accounts
+                            .execute_traced(
+                                client_id,
+                                action.clone(),
+                                traced,
+                                &self.response_sender,
+                            )
+                            .await;
+                        self.report.explain.push(ExplainStep {
+                            line: self.report.stats.lines_read,
+                            text: trimmed.to_string(),
+                            client_id,
+                            action: action.clone(),
+                            outcome: result.as_ref().cloned().map_err(|err| err.to_string()),
+                        });
+                        result.map(|_outcome| ())
+                    }
+                    _ => {
+                        self.accounts
+                            .execute(client_id, action.clone(), &self.response_sender)
+                            .await
+                    }
+                };
+                match result {
+                    Ok(()) => ParseOutcome::Executed,
+                    Err(err) => {
+                        if !self.accounts.is_quiet() {
+                            warn!(
+                                "Transaction refused: {err} (client: {client_id} {:?})",
+                                action
+                            );
+                        }
+                        ParseOutcome::Error(err.to_string())
+                    }
                 }
             }
-            Err(_err) => {
-                warn!("Record skipped due to \"{_err}\" in \"{line}\"");
+            Err(_)
+                if trimmed
+                    .split(delimiter)
+                    .next()
+                    .is_some_and(|field| field.trim().eq_ignore_ascii_case("type")) =>
+            {
+                ParseOutcome::Header
+            }
+            Err(err) => {
+                if !self.accounts.is_quiet() {
+                    warn!("Record skipped due to \"{err}\" in \"{line}\"");
+                }
+                if self.options.include_unparsed_clients {
+                    if let Some(client_id) = line
+                        .split(delimiter)
+                        .nth(1)
+                        .and_then(|field| ClientId::from_str(field.trim()).ok())
+                    {
+                        self.accounts.register(client_id, &self.response_sender);
+                    }
+                }
+                ParseOutcome::Error(err.to_string())
             }
         }
     }
 
-    writer
-        .write_all(b"client,available,held,total,locked\n")
-        .await?;
+    /// Summarizes all started transactions and writes out the results
+    async fn finish<W>(self, writer: &mut W) -> Result<ProcessReport, std::io::Error>
+    where
+        W: AsyncWriteExt + Unpin + Send,
+    {
+        let Self {
+            accounts,
+            options,
+            mut report,
+            started,
+            offset,
+            ..
+        } = self;
+        report.end_offset = offset;
+
+        let columns = ColumnSet::from_options(options);
+        if let Some(dir) = &options.json_dir {
+            tokio::fs::create_dir_all(dir).await?;
+        } else {
+            writer.write_all(columns.header().as_bytes()).await?;
+        }
 
-    //summarize all started transactions
-    let accounts = accounts.summarize().await;
+        //summarize all started transactions
+        //the handle outlives the hub, so the responses dropped during 'summarize' are counted too
+        let handle = accounts.handle();
+        let first_seen = accounts.clients_by_first_seen();
+        let mut accounts = accounts.summarize().await;
+        let quiet = handle.is_quiet();
+        report.stats.dropped_responses = handle.dropped_responses();
+        if report.stats.dropped_responses > 0 && !quiet {
+            warn!(
+                "{} action responses were not logged (the logger fell behind)",
+                report.stats.dropped_responses
+            );
+        }
+        match options.summary_order {
+            SummaryOrder::ByClientId => {} //the accounts are already sorted by client id
+            SummaryOrder::ByHeldDesc => {
+                //the sort is stable, so the ties remain sorted by client id
+                accounts.sort_by_key(|(_, account)| std::cmp::Reverse(account.held()));
+            }
+            SummaryOrder::FirstSeen => {
+                let sequence: HashMap<ClientId, usize> = first_seen
+                    .into_iter()
+                    .enumerate()
+                    .map(|(sequence, client_id)| (client_id, sequence))
+                    .collect();
+                accounts.sort_by_key(|(client_id, _)| sequence.get(client_id).copied());
+            }
+        }
 
-    //write out the report
-    for (client_id, account) in accounts {
-        let summary = format!(
-            "{}, {}, {}, {}, {}\n",
-            client_id,
-            account.available(),
-            account.held(),
-            account.total(),
-            account.is_locked()
-        );
+        report.deposit_stats = match options.deposit_stats {
+            DepositStatsMode::Off => None,
+            DepositStatsMode::Streaming => Some(AmountStats::default()),
+            DepositStatsMode::Buffered => Some(AmountStats::buffered()),
+        };
+
+        //write out the report
+        for (client_id, account) in accounts {
+            report.stats.executed += account.transaction_count();
+            if let Some(stats) = &mut report.deposit_stats {
+                stats.merge(account.deposit_stats());
+            }
+            if options.negative_balance_alerts
+                && (account.available() < Amount::ZERO || account.total() < Amount::ZERO)
+            {
+                if !quiet {
+                    warn!(
+                        "Negative balance: client {client_id} available {} total {}",
+                        account.available(),
+                        account.total()
+                    );
+                }
+                report.negative_balances.push(client_id);
+            }
 
-        if let Err(_err) = writer.write_all(summary.as_bytes()).await {
-            error!("Was unable to write out summary \"{summary}\" due to error: \"{_err}\"");
+            if let Some(dir) = &options.json_dir {
+                let path = dir.join(format!("{client_id}.json"));
+                //the error is returned (with the path), the rest of the files are not written
+                tokio::fs::write(&path, columns.json(client_id, &account))
+                    .await
+                    .map_err(|err| {
+                        std::io::Error::new(
+                            err.kind(),
+                            format!("Was unable to write out summary to {path:?}: {err}"),
+                        )
+                    })?;
+                continue;
+            }
+
+            let summary = columns.row(client_id, &account);
+
+            if let Err(_err) = writer.write_all(summary.as_bytes()).await {
+                if !quiet {
+                    error!(
+                        "Was unable to write out summary \"{summary}\" due to error: \"{_err}\""
+                    );
+                }
+            }
         }
-    }
+        writer.flush().await?;
 
-    Ok(())
+        report.stats.refused = report.outcomes.error;
+        report.stats.elapsed = started.elapsed();
+        Ok(report)
+    }
 }
 
 #[cfg(test)]
@@ -248,16 +992,1045 @@ dispute, 2, 5,
     #[tokio::test]
     async fn full_integration_test() {
         let mut summary_buff = Vec::<u8>::new();
+        assert!(process_csv(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut summary_buff
+        )
+        .await
+        .is_ok());
+        assert_eq!(summary_buff, OUTPUT);
+    }
+
+    /// The lines of client 50 from INPUT one by one: repeated dispute/resolve/chargeback cycles on tx 63
+    #[tokio::test]
+    async fn redispute_sequence() {
+        use TransactionError::*;
+
+        //line, result, state of tx 63 after it, available, held, locked
+        type Step = (
+            &'static str,
+            Result<(), TransactionError>,
+            Option<&'static str>,
+            &'static str,
+            &'static str,
+            bool,
+        );
+        #[rustfmt::skip]
+        let steps: &[Step] = &[
+            ("withdrawal, 50, 61, 0", Err(InvalidAmount), None, "0", "0", false),
+            ("withdrawal, 50, 62, 1", Err(InvalidAmount), None, "0", "0", false),
+            ("deposit, 50, 63, 100", Ok(()), Some("Deposit(100)"), "100", "0", false),
+            ("withdrawal, 50, 64, 0", Err(InvalidAmount), Some("Deposit(100)"), "100", "0", false),
+            ("withdrawal, 50, 65, 5", Ok(()), Some("Deposit(100)"), "95", "0", false),
+            ("withdrawal, 50, 66, 99", Err(InvalidAmount), Some("Deposit(100)"), "95", "0", false),
+            ("deposit, 50, 67, 200.124", Ok(()), Some("Deposit(100)"), "295.124", "0", false),
+            ("deposit, 50, 68, 1.00000", Ok(()), Some("Deposit(100)"), "296.124", "0", false),
+            //nothing to resolve or charge back before the first dispute
+            ("resolve, 50, 63,", Err(DisputeNotOpenedYet), Some("Deposit(100)"), "296.124", "0", false),
+            ("chargeback, 50, 63,", Err(DisputeNotOpenedYet), Some("Deposit(100)"), "296.124", "0", false),
+            //ids of other clients, refused transactions and withdrawals can not be disputed
+            ("resolve, 50, 3,", Err(InvalidTransactionId), Some("Deposit(100)"), "296.124", "0", false),
+            ("chargeback, 50, 2,", Err(InvalidTransactionId), Some("Deposit(100)"), "296.124", "0", false),
+            ("dispute, 50, 62", Err(InvalidTransactionId), Some("Deposit(100)"), "296.124", "0", false),
+            ("dispute, 50, 65", Err(InvalidTransactionType), Some("Deposit(100)"), "296.124", "0", false),
+            ("deposit, 50, 67, 200", Err(RepeatedTransactionId), Some("Deposit(100)"), "296.124", "0", false),
+            //first cycle: dispute, resolve
+            ("dispute, 50, 63", Ok(()), Some("DepositInDispute(100)"), "196.124", "100", false),
+            ("dispute, 50, 66", Err(InvalidTransactionId), Some("DepositInDispute(100)"), "196.124", "100", false),
+            ("dispute, 50, 63,", Err(AlreadyInDispute), Some("DepositInDispute(100)"), "196.124", "100", false),
+            ("resolve, 50, 63,", Ok(()), Some("Deposit(100)"), "296.124", "0", false),
+            ("chargeback, 50, 63,", Err(DisputeNotOpenedYet), Some("Deposit(100)"), "296.124", "0", false),
+            ("resolve, 50, 63,", Err(DisputeNotOpenedYet), Some("Deposit(100)"), "296.124", "0", false),
+            //second cycle: dispute again, charge back (locks the account)
+            ("dispute, 50, 63,", Ok(()), Some("DepositInDispute(100)"), "196.124", "100", false),
+            ("chargeback, 50, 63,", Ok(()), Some("ChargedBack(100)"), "196.124", "0", true),
+            ("chargeback, 50, 63,", Err(AlreadyChargedBack), Some("ChargedBack(100)"), "196.124", "0", true),
+            ("deposit, 50, 71, 200,", Err(AccountLocked), Some("ChargedBack(100)"), "196.124", "0", true),
+            ("withdrawal, 50, 72, 1,", Err(AccountLocked), Some("ChargedBack(100)"), "196.124", "0", true),
+        ];
+
+        let options = CsvOptions::default();
+        let mut account = Account::new(InMemoryLedger::connect().unwrap());
+        for (line, result, state, available, held, locked) in steps {
+            let (client_id, action) = parse_csv_line(line, &options).unwrap();
+            assert_eq!(client_id, ClientId::from(50), "{line}");
+            assert_eq!(account.execute(action).await, *result, "{line}");
+            assert_eq!(
+                account
+                    .transaction_state(TransactionId::from(63))
+                    .await
+                    .unwrap()
+                    .map(|state| state.to_string())
+                    .as_deref(),
+                *state,
+                "{line}"
+            );
+            let available = Amount::from_str(available).unwrap();
+            let held = Amount::from_str(held).unwrap();
+            assert_eq!(account.available(), available, "{line}");
+            assert_eq!(account.held(), held, "{line}");
+            assert_eq!(
+                account.total(),
+                Amount::checked_add(available, held).unwrap(),
+                "{line}"
+            );
+            assert_eq!(account.is_locked(), *locked, "{line}");
+        }
+        //the last line of the client is malformed (missing separator)
         assert_eq!(
-            process_csv(
+            parse_csv_line("chargeback 50, 67", &options),
+            Err(ParseError)
+        );
+        //matches the row of client 50 in OUTPUT
+        assert_eq!(account.total(), Amount::from_str("196.124").unwrap());
+    }
+
+    #[tokio::test]
+    async fn deposit_statistics() {
+        let input = b"type, client, tx, amount
+deposit, 1, 1, 1.5
+deposit, 2, 2, 4
+deposit, 1, 3, 10
+withdrawal, 1, 4, 2
+deposit, 2, 5, -3
+deposit, 1, 1, 100
+deposit, 3, 6, 0.5
+dispute, 2, 2
+deposit, 3, 7, 0.0001
+";
+        let run = |deposit_stats| async move {
+            let options = CsvOptions {
+                deposit_stats,
+                ..CsvOptions::default()
+            };
+            process_csv_with(
+                AccountHub::new(|_client_id| InMemoryLedger::connect()),
+                &input[..],
+                &mut Vec::<u8>::new(),
+                &options,
+            )
+            .await
+            .unwrap()
+            .deposit_stats
+        };
+        let amount = |s| Amount::from_str(s).unwrap();
+
+        assert_eq!(run(DepositStatsMode::Off).await, None);
+
+        //the accepted deposits: 1.5, 4, 10, 0.5, 0.0001 (the refused and repeated ones are not counted)
+        let stats = run(DepositStatsMode::Streaming).await.unwrap();
+        assert_eq!(stats.count(), 5);
+        assert_eq!(stats.min(), Some(amount("0.0001")));
+        assert_eq!(stats.max(), Some(amount("10")));
+        //16.0001 / 5 = 3.20002, rounded toward zero
+        assert_eq!(stats.mean(), Some(amount("3.2")));
+        assert_eq!(stats.median(), None);
+
+        let stats = run(DepositStatsMode::Buffered).await.unwrap();
+        assert_eq!(stats.count(), 5);
+        assert_eq!(stats.mean(), Some(amount("3.2")));
+        assert_eq!(stats.median(), Some(amount("1.5")));
+    }
+
+    #[tokio::test]
+    async fn explain_transaction() {
+        let options = CsvOptions {
+            explain: Some(TransactionId::from(63)),
+            ..CsvOptions::default()
+        };
+        let mut summary_buff = Vec::<u8>::new();
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut summary_buff,
+            &options,
+        )
+        .await
+        .unwrap();
+        //tracing does not change the results
+        assert_eq!(summary_buff, OUTPUT);
+
+        let trace: Vec<_> = report.explain.iter().map(|step| step.to_string()).collect();
+        assert_eq!(
+            trace,
+            vec![
+                "line 57 \"deposit, 50, 63, 100\": ok, tx: Deposit(100), available: 100, held: 0, total: 100, locked: false",
+                "line 63 \"resolve, 50, 63,\": DisputeNotOpenedYet, tx: Deposit(100), available: 296.124, held: 0, total: 296.124, locked: false",
+                "line 64 \"chargeback, 50, 63,\": DisputeNotOpenedYet, tx: Deposit(100), available: 296.124, held: 0, total: 296.124, locked: false",
+                "line 70 \"dispute, 50, 63\": ok, tx: DepositInDispute(100), available: 196.124, held: 100, total: 296.124, locked: false",
+                "line 72 \"dispute, 50, 63,\": AlreadyInDispute, tx: DepositInDispute(100), available: 196.124, held: 100, total: 296.124, locked: false",
+                "line 73 \"resolve, 50, 63,\": ok, tx: Deposit(100), available: 296.124, held: 0, total: 296.124, locked: false",
+                "line 74 \"chargeback, 50, 63,\": DisputeNotOpenedYet, tx: Deposit(100), available: 296.124, held: 0, total: 296.124, locked: false",
+                "line 75 \"resolve, 50, 63,\": DisputeNotOpenedYet, tx: Deposit(100), available: 296.124, held: 0, total: 296.124, locked: false",
+                "line 76 \"dispute, 50, 63,\": ok, tx: DepositInDispute(100), available: 196.124, held: 100, total: 296.124, locked: false",
+                "line 77 \"chargeback, 50, 63,\": ok, tx: ChargedBack(100), available: 196.124, held: 0, total: 196.124, locked: true",
+                "line 78 \"chargeback, 50, 63,\": AlreadyChargedBack, tx: ChargedBack(100), available: 196.124, held: 0, total: 196.124, locked: true",
+            ]
+        );
+        assert!(report
+            .explain
+            .iter()
+            .all(|step| step.client_id == ClientId::from(50)
+                && step.action.transaction_id() == Some(TransactionId::from(63))));
+
+        //nothing is traced by default
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut Vec::<u8>::new(),
+            &CsvOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert!(report.explain.is_empty());
+    }
+
+    #[tokio::test]
+    async fn explain_references() {
+        let options = CsvOptions {
+            explain: Some(TransactionId::from(7)),
+            references: true,
+            ..CsvOptions::default()
+        };
+        let input = b"type,client,tx,amount\n\
+            deposit,1,7,10,INV-007\n\
+            dispute,1,7\n\
+            deposit,1,8,5,\"INV 008\"\n\
+            withdrawal,1,7,1,INV-007\n";
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            &input[..],
+            &mut Vec::<u8>::new(),
+            &options,
+        )
+        .await
+        .unwrap();
+        let trace: Vec<_> = report.explain.iter().map(|step| step.to_string()).collect();
+        assert_eq!(
+            trace,
+            vec![
+                "line 2 \"deposit,1,7,10,INV-007\": ok, tx: Deposit(10), ref: INV-007, available: 10, held: 0, total: 10, locked: false",
+                "line 3 \"dispute,1,7\": ok, tx: DepositInDispute(10), ref: INV-007, available: 0, held: 10, total: 10, locked: false",
+                "line 5 \"withdrawal,1,7,1,INV-007\": RepeatedTransactionId, tx: DepositInDispute(10), ref: INV-007, available: 5, held: 10, total: 15, locked: false",
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn line_outcomes() {
+        let mut summary_buff = Vec::<u8>::new();
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut summary_buff,
+            &CsvOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            report.outcomes,
+            OutcomeCounts {
+                executed: 51,
+                header: 1,
+                comment: 0,
+                blank: 9,
+                error: 30,
+            }
+        );
+        assert_eq!(report.stats.lines_read, 91);
+
+        const COMMENTED: &[u8] = b"type, client, tx, amount\n// the first deposit\n\n  \ndeposit, 1, 1, 1\ndeposit, x, 2, 1\n";
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect())
+                .with_transaction_limit(0, LimitResponse::RefuseClient),
+            COMMENTED,
+            &mut Vec::<u8>::new(),
+            &CsvOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            report.outcomes,
+            OutcomeCounts {
+                executed: 0,
+                header: 1,
+                comment: 1,
+                blank: 2,
+                error: 2,
+            }
+        );
+    }
+
+    /// The accounts progress with different speeds, so their actions are interleaved differently
+    /// than without delays, but the actions of each account are still executed in order.
+    #[cfg(feature = "simulate-delays")]
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn delayed_integration_test() {
+        fn connect(client_id: ClientId) -> Option<InMemoryLedger> {
+            let millis = client_id.to_string().len() as u64 * 3 % 5;
+            InMemoryLedger::connect_with_delay(Duration::from_millis(millis + 1))
+        }
+        for permits in [None, Some(2)] {
+            let mut accounts = AccountHub::new(connect);
+            if let Some(permits) = permits {
+                accounts = accounts.with_task_permits(permits);
+            }
+            let mut summary_buff = Vec::<u8>::new();
+            process_csv(accounts, INPUT, &mut summary_buff)
+                .await
+                .unwrap();
+            assert_eq!(summary_buff, OUTPUT);
+        }
+    }
+
+    #[test]
+    fn quoting() {
+        assert_eq!(quote_csv_field("1.5", ','), "1.5");
+        assert_eq!(quote_csv_field("North, East", ','), "\"North, East\"");
+        assert_eq!(quote_csv_field("North; East", ','), "North; East");
+        assert_eq!(quote_csv_field("North; East", ';'), "\"North; East\"");
+        assert_eq!(quote_csv_field("say \"hi\"", ','), "\"say \"\"hi\"\"\"");
+        assert_eq!(quote_csv_field("two\nlines", ','), "\"two\nlines\"");
+
+        //e.g. a metadata field containing the delimiter
+        assert_eq!(
+            csv_row(&["7", "1.5", "0", "1.5", "false", "Budapest, HU"]),
+            "7, 1.5, 0, 1.5, false, \"Budapest, HU\"\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn in_memory_lines() {
+        let lines = vec![
+            "type, client, tx, amount".to_string(),
+            "deposit, 2, 1, 10".to_string(),
+            "deposit, 1, 2, 2.5".to_string(),
+            "withdrawal, 2, 3, 4".to_string(),
+            "dispute, 1, 2".to_string(),
+        ];
+        let mut summary_buff = Vec::<u8>::new();
+        assert!(process_lines(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            lines,
+            &mut summary_buff
+        )
+        .await
+        .is_ok());
+        assert_eq!(
+            String::from_utf8(summary_buff).unwrap(),
+            "client,available,held,total,locked\n1, 0, 2.5, 2.5, false\n2, 6, 0, 6, false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn header_matches_rows() {
+        for transaction_count in [false, true] {
+            let options = CsvOptions {
+                transaction_count,
+                ..CsvOptions::default()
+            };
+            let mut summary_buff = Vec::<u8>::new();
+            process_csv_with(
+                AccountHub::new(|_client_id| InMemoryLedger::connect()),
+                INPUT,
+                &mut summary_buff,
+                &options,
+            )
+            .await
+            .unwrap();
+
+            let summary = String::from_utf8(summary_buff).unwrap();
+            let mut lines = summary.lines();
+            let header_fields = lines.next().unwrap().split(',').count();
+            assert_eq!(
+                header_fields,
+                ColumnSet::from_options(&options).columns().len()
+            );
+            for row in lines {
+                assert_eq!(row.split(',').count(), header_fields, "{row}");
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn resumed_processing() {
+        //interrupt the processing somewhere in the middle (at the beginning of a line)
+        let half = INPUT.len() / 2;
+        let half = half + INPUT[half..].iter().position(|&c| c == b'\n').unwrap() + 1;
+
+        let options = CsvOptions::default();
+        let mut processor = CsvProcessor::new(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            &options,
+        );
+        let offset = processor.read(&INPUT[..half]).await.unwrap();
+        assert_eq!(offset, half as u64);
+
+        //resume reading the whole input from the recorded offset
+        assert_eq!(processor.read(INPUT).await.unwrap(), INPUT.len() as u64);
+        let mut summary_buff = Vec::<u8>::new();
+        let report = processor.finish(&mut summary_buff).await.unwrap();
+        assert_eq!(summary_buff, OUTPUT);
+        assert_eq!(report.end_offset, INPUT.len() as u64);
+        assert_eq!(
+            report.stats.lines_read,
+            INPUT.iter().filter(|&&c| c == b'\n').count() as u64
+        );
+
+        //the second half alone
+        let options = CsvOptions {
+            start_offset: offset,
+            ..CsvOptions::default()
+        };
+        let mut summary_buff = Vec::<u8>::new();
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut summary_buff,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.end_offset, INPUT.len() as u64);
+        assert_eq!(
+            report.stats.lines_read,
+            INPUT[half..].iter().filter(|&&c| c == b'\n').count() as u64
+        );
+
+        //offset beyond the end of input
+        let options = CsvOptions {
+            start_offset: INPUT.len() as u64 + 1,
+            ..CsvOptions::default()
+        };
+        assert!(process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut Vec::<u8>::new(),
+            &options,
+        )
+        .await
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn json_files() {
+        let dir = std::env::temp_dir().join(format!("accounter-json-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let options = CsvOptions {
+            json_dir: Some(dir.join("summary")),
+            transaction_count: true,
+            ..CsvOptions::default()
+        };
+        let mut summary_buff = Vec::<u8>::new();
+        assert!(process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut summary_buff,
+            &options
+        )
+        .await
+        .is_ok());
+        assert!(summary_buff.is_empty());
+
+        let mut files: Vec<_> = std::fs::read_dir(dir.join("summary"))
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        assert_eq!(files, ["1.json", "10.json", "2.json", "50.json"]);
+        assert_eq!(
+            std::fs::read_to_string(dir.join("summary").join("1.json")).unwrap(),
+            "{\"client\": 1, \"available\": -0.8, \"held\": 0, \"total\": -0.8, \"locked\": true, \"transactions\": 10}\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.join("summary").join("2.json")).unwrap(),
+            "{\"client\": 2, \"available\": 15, \"held\": 5, \"total\": 20, \"locked\": false, \"transactions\": 5}\n"
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn json_file_error() {
+        let dir = std::env::temp_dir().join(format!("accounter-json-err-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        //the file of client 2 can not be written, since a directory has its name
+        std::fs::create_dir_all(dir.join("2.json")).unwrap();
+        let options = CsvOptions {
+            json_dir: Some(dir.clone()),
+            ..CsvOptions::default()
+        };
+        let result = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut Vec::<u8>::new(),
+            &options,
+        )
+        .await;
+        assert!(result.unwrap_err().to_string().contains("2.json"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unparsed_clients() {
+        const INPUT: &[u8] = br###"type, client, tx, amount
+deposit, 1, 1, 5
+deposit, 7, 2, five
+withdrawal, 8
+"###;
+        for include_unparsed_clients in [false, true] {
+            let options = CsvOptions {
+                include_unparsed_clients,
+                ..CsvOptions::default()
+            };
+            let mut summary_buff = Vec::<u8>::new();
+            process_csv_with(
+                AccountHub::new(|_client_id| InMemoryLedger::connect()),
+                INPUT,
+                &mut summary_buff,
+                &options,
+            )
+            .await
+            .unwrap();
+            let expected = if include_unparsed_clients {
+                "client,available,held,total,locked\n1, 5, 0, 5, false\n7, 0, 0, 0, false\n8, 0, 0, 0, false\n"
+            } else {
+                "client,available,held,total,locked\n1, 5, 0, 5, false\n"
+            };
+            assert_eq!(String::from_utf8(summary_buff).unwrap(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn delimiters_integration_test() {
+        for delimiter in [Delimiter::Semicolon, Delimiter::Tab] {
+            let input = String::from_utf8(INPUT.to_vec())
+                .unwrap()
+                .replace(',', &delimiter.as_char().to_string());
+            let options = CsvOptions {
+                delimiter,
+                ..CsvOptions::default()
+            };
+            let mut summary_buff = Vec::<u8>::new();
+            assert!(process_csv_with(
+                AccountHub::new(|_client_id| InMemoryLedger::connect()),
+                input.as_bytes(),
+                &mut summary_buff,
+                &options
+            )
+            .await
+            .is_ok());
+            assert_eq!(summary_buff, OUTPUT, "{delimiter:?}");
+
+            //other delimiters are not accepted
+            let mut summary_buff = Vec::<u8>::new();
+            assert!(process_csv_with(
+                AccountHub::new(|_client_id| InMemoryLedger::connect()),
+                INPUT,
+                &mut summary_buff,
+                &options
+            )
+            .await
+            .is_ok());
+            assert_eq!(summary_buff, b"client,available,held,total,locked\n");
+        }
+    }
+
+    #[test]
+    fn parse_delimited_lines() {
+        let semicolon = CsvOptions {
+            delimiter: Delimiter::Semicolon,
+            locale: NumberLocale::EuDe,
+            ..CsvOptions::default()
+        };
+        let tab = CsvOptions {
+            delimiter: Delimiter::Tab,
+            ..CsvOptions::default()
+        };
+        let deposit = |amount: &str| {
+            Ok((
+                ClientId::from(1),
+                Action::Transact(
+                    (
+                        TransactionId::from(2),
+                        Transaction::Deposit(Amount::from_str(amount).unwrap()),
+                    ),
+                    None,
+                ),
+            ))
+        };
+        assert_eq!(
+            parse_csv_line("deposit; 1 ;2; 1.234,5", &semicolon),
+            deposit("1234.5")
+        );
+        assert_eq!(
+            parse_csv_line("deposit;1;2;\"1.234,5\";", &semicolon),
+            deposit("1234.5")
+        );
+        assert_eq!(parse_csv_line("deposit,1,2,1", &semicolon), Err(ParseError));
+        assert_eq!(parse_csv_line("deposit\t1\t2\t1.5", &tab), deposit("1.5"));
+        assert_eq!(
+            parse_csv_line("deposit\t 1 \t 2\t1.5 \t", &tab),
+            deposit("1.5")
+        );
+        //tabs are not whitespaces if those are the delimiters
+        assert_eq!(
+            parse_csv_line("deposit\t\t1\t2\t1.5", &tab),
+            Err(ParseError)
+        );
+        assert_eq!(
+            parse_csv_line("deposit,\t1,\t2,\t1.5", &CsvOptions::default()),
+            deposit("1.5")
+        );
+        assert_eq!(
+            parse_csv_line("type; client; tx; amount", &semicolon),
+            Err(ParseError)
+        );
+    }
+
+    #[tokio::test]
+    async fn action_aliases() {
+        let options = CsvOptions {
+            aliases: BTreeMap::from([
+                ("credit".to_string(), ActionKind::Deposit),
+                ("debit".to_string(), ActionKind::Withdrawal),
+            ]),
+            ..CsvOptions::default()
+        };
+        assert_eq!(
+            parse_csv_line(" credit , 1, 2, 1.5", &options),
+            parse_csv_line("deposit, 1, 2, 1.5", &options)
+        );
+        assert_eq!(
+            parse_csv_line("debit,1,2,1.5", &options),
+            parse_csv_line("withdrawal,1,2,1.5", &options)
+        );
+        //the canonical keywords are still accepted, unknown words are not
+        assert!(parse_csv_line("deposit, 1, 2, 1.5", &options).is_ok());
+        assert_eq!(parse_csv_line("refund, 1, 2", &options), Err(ParseError));
+        assert_eq!(parse_csv_line("credit", &options), Err(ParseError));
+        //not enabled by default
+        assert_eq!(
+            parse_csv_line("credit, 1, 2, 1.5", &CsvOptions::default()),
+            Err(ParseError)
+        );
+
+        let input = b"type, client, tx, amount
+credit, 1, 1, 10
+debit, 1, 2, 2.5
+credit, 2, 3, 4
+dispute, 2, 3
+refund, 1, 4, 1
+";
+        let mut summary_buff = Vec::<u8>::new();
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            &input[..],
+            &mut summary_buff,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            String::from_utf8(summary_buff).unwrap(),
+            "client,available,held,total,locked\n1, 7.5, 0, 7.5, false\n2, 0, 4, 4, false\n"
+        );
+        assert_eq!(report.outcomes.executed, 4);
+        assert_eq!(report.outcomes.error, 1);
+    }
+
+    #[test]
+    fn parse_accounting_negatives() {
+        let accounting = CsvOptions {
+            accounting_negatives: true,
+            ..CsvOptions::default()
+        };
+        let withdrawal = |amount: &str| {
+            Ok((
+                ClientId::from(1),
+                Action::Transact(
+                    (
+                        TransactionId::from(2),
+                        Transaction::Withdrawal(Amount::from_str(amount).unwrap()),
+                    ),
+                    None,
+                ),
+            ))
+        };
+        assert_eq!(
+            parse_csv_line("withdrawal, 1, 2, (1.50)", &accounting),
+            withdrawal("-1.5")
+        );
+        assert_eq!(
+            parse_csv_line("withdrawal, 1, 2, \"(1,234.50)\"", &accounting),
+            withdrawal("-1234.5")
+        );
+        assert_eq!(
+            parse_csv_line("withdrawal, 1, 2, (0)", &accounting),
+            withdrawal("0")
+        );
+        assert_eq!(
+            parse_csv_line("withdrawal, 1, 2, 1.50", &accounting),
+            withdrawal("1.5")
+        );
+        for malformed in [
+            "withdrawal, 1, 2, (1.50",
+            "withdrawal, 1, 2, 1.50)",
+            "withdrawal, 1, 2, (-1.50)",
+            "withdrawal, 1, 2, ((1.50))",
+        ] {
+            assert_eq!(
+                parse_csv_line(malformed, &accounting),
+                Err(ParseError),
+                "{malformed}"
+            );
+        }
+        //disabled by default
+        assert_eq!(
+            parse_csv_line("withdrawal, 1, 2, (1.50)", &CsvOptions::default()),
+            Err(ParseError)
+        );
+    }
+
+    #[test]
+    fn parse_references() {
+        let options = CsvOptions {
+            references: true,
+            ..CsvOptions::default()
+        };
+        let plain = Ok((
+            ClientId::from(1),
+            Action::Transact(
+                (
+                    TransactionId::from(2),
+                    Transaction::Withdrawal(Amount::from_str("1.5").unwrap()),
+                ),
+                None,
+            ),
+        ));
+        let withdrawal = |reference: &str| {
+            Ok((
+                ClientId::from(1),
+                Action::Transact(
+                    (
+                        TransactionId::from(2),
+                        Transaction::Withdrawal(Amount::from_str("1.5").unwrap()),
+                    ),
+                    Some(reference.to_string()),
+                ),
+            ))
+        };
+        assert_eq!(
+            parse_csv_line("withdrawal, 1, 2, 1.5, INV-001", &options),
+            withdrawal("INV-001")
+        );
+        assert_eq!(
+            parse_csv_line("withdrawal,1,2,1.5,INV-001 ,comment", &options),
+            withdrawal("INV-001")
+        );
+        assert_eq!(
+            parse_csv_line("withdrawal,1,2,1.5,\"a;b c\"", &options),
+            withdrawal("a;b c")
+        );
+        assert_eq!(
+            parse_csv_line(
+                "withdrawal\t1\t2\t1.5\tINV,001",
+                &CsvOptions {
+                    delimiter: Delimiter::Tab,
+                    ..options.clone()
+                }
+            ),
+            withdrawal("INV,001")
+        );
+        //by default the fifth field is a comment
+        assert_eq!(
+            parse_csv_line("withdrawal, 1, 2, 1.5, INV-001", &CsvOptions::default()),
+            plain
+        );
+        //empty fields, free text and "//" comments are comments too
+        for line in [
+            "withdrawal,1,2,1.5,",
+            "withdrawal,1,2,1.5,refund of INV-001",
+            "withdrawal,1,2,1.5,INV 001 ,comment",
+            "withdrawal, 1, 2, 1.5,    // => 1",
+        ] {
+            assert_eq!(parse_csv_line(line, &options), plain, "{line}");
+        }
+        //an over-long reference is refused (it is a comment only if references are not read)
+        let max = "x".repeat(Reference::MAX_LEN);
+        assert_eq!(
+            parse_csv_line(&format!("withdrawal,1,2,1.5,{max}"), &options),
+            withdrawal(&max)
+        );
+        let over_long = format!("withdrawal,1,2,1.5,\"{max}x\"");
+        assert_eq!(parse_csv_line(&over_long, &options), Err(ParseError));
+        assert_eq!(parse_csv_line(&over_long, &CsvOptions::default()), plain);
+        //arbitrations have no references, their extra fields are comments
+        assert_eq!(
+            parse_csv_line("dispute,1,2,INV-001", &options),
+            Ok((ClientId::from(1), Action::Dispute(TransactionId::from(2))))
+        );
+    }
+
+    #[tokio::test]
+    async fn transaction_count_column() {
+        const OUTPUT: &[u8] = br###"client,available,held,total,locked,transactions
+1, -0.8, 0, -0.8, true, 10
+2, 15, 5, 20, false, 5
+10, 922337203685477.5807, 0, 922337203685477.5807, false, 4
+50, 196.124, 0, 196.124, true, 8
+"###;
+
+        let mut summary_buff = Vec::<u8>::new();
+        let options = CsvOptions {
+            transaction_count: true,
+            ..CsvOptions::default()
+        };
+        assert!(process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut summary_buff,
+            &options
+        )
+        .await
+        .is_ok());
+        assert_eq!(summary_buff, OUTPUT);
+    }
+
+    #[tokio::test]
+    async fn summary_by_held_desc() {
+        const INPUT: &[u8] = br###"type, client, tx, amount
+deposit, 1, 1, 5
+deposit, 2, 2, 20
+deposit, 3, 3, 7
+deposit, 4, 4, 20
+deposit, 5, 5, 1
+dispute, 1, 1
+dispute, 2, 2
+dispute, 3, 3
+dispute, 4, 4
+"###;
+        const OUTPUT: &[u8] = br###"client,available,held,total,locked
+2, 0, 20, 20, false
+4, 0, 20, 20, false
+3, 0, 7, 7, false
+1, 0, 5, 5, false
+5, 1, 0, 1, false
+"###;
+
+        let options = CsvOptions {
+            summary_order: SummaryOrder::ByHeldDesc,
+            ..CsvOptions::default()
+        };
+        let mut summary_buff = Vec::<u8>::new();
+        assert!(process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut summary_buff,
+            &options
+        )
+        .await
+        .is_ok());
+        assert_eq!(summary_buff, OUTPUT);
+    }
+
+    #[tokio::test]
+    async fn summary_by_first_seen() {
+        const INPUT: &[u8] = br###"type, client, tx, amount
+deposit, 50, 1, 5
+deposit, 10, 2, 1
+deposit, 50, 3, 1
+malformed, 7, 4, 1
+deposit, 30, 5, 2
+withdrawal, 10, 6, 1
+"###;
+        let mut outputs = Vec::new();
+        for summary_order in [SummaryOrder::ByClientId, SummaryOrder::FirstSeen] {
+            let options = CsvOptions {
+                summary_order,
+                include_unparsed_clients: true,
+                ..CsvOptions::default()
+            };
+            let mut summary_buff = Vec::<u8>::new();
+            process_csv_with(
                 AccountHub::new(|_client_id| InMemoryLedger::connect()),
                 INPUT,
-                &mut summary_buff
+                &mut summary_buff,
+                &options,
             )
             .await
-            .is_ok(),
-            true
+            .unwrap();
+            outputs.push(String::from_utf8(summary_buff).unwrap());
+        }
+        assert_eq!(
+            outputs[0],
+            "client,available,held,total,locked\n\
+             7, 0, 0, 0, false\n\
+             10, 0, 0, 0, false\n\
+             30, 2, 0, 2, false\n\
+             50, 6, 0, 6, false\n"
+        );
+        assert_eq!(
+            outputs[1],
+            "client,available,held,total,locked\n\
+             50, 6, 0, 6, false\n\
+             10, 0, 0, 0, false\n\
+             7, 0, 0, 0, false\n\
+             30, 2, 0, 2, false\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn negative_balance_alerts() {
+        let options = CsvOptions {
+            negative_balance_alerts: true,
+            ..CsvOptions::default()
+        };
+        let mut summary_buff = Vec::<u8>::new();
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut summary_buff,
+            &options,
+        )
+        .await
+        .unwrap();
+        assert_eq!(summary_buff, OUTPUT);
+        assert_eq!(report.negative_balances, vec![ClientId::from(1)]);
+
+        //not collected by default
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut Vec::<u8>::new(),
+            &CsvOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert!(report.negative_balances.is_empty());
+    }
+
+    #[tokio::test]
+    async fn process_stats() {
+        let report = process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut Vec::<u8>::new(),
+            &CsvOptions::default(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(report.stats.lines_read, 91);
+        assert_eq!(report.stats.executed, 27);
+        assert_eq!(report.stats.refused, 30);
+        assert_eq!(report.stats.refused, report.outcomes.error);
+
+        let stats = ProcessStats {
+            elapsed: Duration::from_millis(2500),
+            ..report.stats
+        };
+        assert_eq!(
+            stats.to_string(),
+            "lines read: 91, executed: 27, refused: 30, elapsed: 2.500s, lines/sec: 36"
         );
+        assert_eq!(
+            ProcessStats::default().to_string(),
+            "lines read: 0, executed: 0, refused: 0, elapsed: 0.000s, lines/sec: 0"
+        );
+    }
+
+    #[tokio::test]
+    async fn synthetic_input_is_deterministic() {
+        let input = synthetic_csv(1024);
+        assert_eq!(input, synthetic_csv(1024));
+        assert_eq!(input.lines().count(), 1025);
+
+        let mut outputs = Vec::new();
+        for _ in 0..2 {
+            let mut summary_buff = Vec::<u8>::new();
+            process_csv(
+                AccountHub::new(|_client_id| InMemoryLedger::connect()),
+                input.as_bytes(),
+                &mut summary_buff,
+            )
+            .await
+            .unwrap();
+            outputs.push(String::from_utf8(summary_buff).unwrap());
+        }
+        assert_eq!(outputs[0], outputs[1]);
+
+        let summary: Vec<_> = outputs[0].lines().collect();
+        assert_eq!(summary.len(), 65);
+        assert_eq!(summary[1], "1, 3849.384, 0, 3849.384, false");
+        //every generated line is well formed
+        for line in input.lines().skip(1) {
+            assert!(parse_csv_line(line, &CsvOptions::default()).is_ok());
+        }
+    }
+
+    #[tokio::test]
+    async fn locale_integration_test() {
+        const INPUT: &[u8] = br###"type, client, tx, amount
+deposit, 1, 1, "1.234,56"
+deposit, 2, 2, 1.234
+deposit, 2, 3, 0,5
+deposit, 3, 4, "1,234.56"
+deposit, 3, 5, 1.5
+"###;
+        const OUTPUT: &[u8] = br###"client,available,held,total,locked
+1, 1234.56, 0, 1234.56, false
+2, 1234, 0, 1234, false
+"###;
+
+        let mut summary_buff = Vec::<u8>::new();
+        let options = CsvOptions {
+            locale: NumberLocale::EuDe,
+            ..CsvOptions::default()
+        };
+        assert!(process_csv_with(
+            AccountHub::new(|_client_id| InMemoryLedger::connect()),
+            INPUT,
+            &mut summary_buff,
+            &options
+        )
+        .await
+        .is_ok());
         assert_eq!(summary_buff, OUTPUT);
     }
+
+    #[test]
+    fn parse_localized_amounts() {
+        let en_us = CsvOptions::default();
+        let eu_de = CsvOptions {
+            locale: NumberLocale::EuDe,
+            ..CsvOptions::default()
+        };
+        let expected = Ok((
+            ClientId::from(1),
+            Action::Transact(
+                (
+                    TransactionId::from(2),
+                    Transaction::Deposit(Amount::from_str("1234.56").unwrap()),
+                ),
+                None,
+            ),
+        ));
+        assert_eq!(parse_csv_line("deposit, 1, 2, 1234.56", &en_us), expected);
+        assert_eq!(
+            parse_csv_line("deposit, 1, 2, \"1,234.56\"", &en_us),
+            expected
+        );
+        assert_eq!(
+            parse_csv_line("deposit, 1, 2, \"1.234,56\"", &eu_de),
+            expected
+        );
+        assert_eq!(
+            parse_csv_line("deposit, 1, 2, \"1.234,56\"", &en_us),
+            Err(ParseError)
+        );
+        assert_eq!(
+            parse_csv_line("deposit, 1, 2, 1234.56", &eu_de),
+            Err(ParseError)
+        );
+    }
 }
@@ -1,9 +1,13 @@
 use clap::Parser;
 use log::error;
+use std::path::PathBuf;
 use std::process;
 use tokio::fs::File;
 
+use accounter::config::*;
 use accounter::in_memory_ledger::*;
+#[cfg(feature = "sled")]
+use accounter::sled_ledger::*;
 use accounter::*;
 
 #[derive(Parser, Debug)]
@@ -22,38 +26,139 @@ struct Args {
     /// [possible values: Auto | Never | Always]
     #[clap(short('s'), long, env("ACCOUNTS_LOG_STYLE"))]
     log_style: Option<String>,
+
+    /// Print throughput statistics to stderr at the end of the run
+    #[clap(long)]
+    stats: bool,
+
+    /// Run configuration file (TOML, or JSON with ".json" extension),
+    /// the command line options override its settings
+    #[clap(short('c'), long)]
+    config: Option<PathBuf>,
+
+    /// Skip this many bytes of the transactions file (to resume an interrupted run)
+    #[clap(long)]
+    start_offset: Option<u64>,
+
+    /// Write the summary of each client into "<client_id>.json" in this directory instead of stdout
+    #[clap(long)]
+    json_dir: Option<PathBuf>,
+
+    /// Print the actions of this transaction id to stderr at the end of the run,
+    /// with their outcomes and the resulting balances
+    #[clap(long, value_name("TX_ID"))]
+    explain: Option<u32>,
+
+    /// Suppress all output on stderr (overrides the log level and the statistics)
+    #[clap(short('q'), long)]
+    quiet: bool,
 }
 
+/// The database of the sled ledgers (the ledger connector can not capture it)
+#[cfg(feature = "sled")]
+static SLED_DB: std::sync::OnceLock<sled::Db> = std::sync::OnceLock::new();
+
 fn main() {
     dotenv::dotenv().ok(); //looks for .env file in the current and parent folders to set up environment variables
     let args = Args::parse(); //reads command arguments (which may come from environment variables too)
 
+    let log_level = if args.quiet {
+        String::from("off")
+    } else {
+        args.log_level.clone().unwrap_or_default()
+    };
+
     pretty_env_logger::formatted_builder()
-        .parse_filters(&args.log_level.unwrap_or(String::default()))
-        .parse_write_style(&args.log_style.unwrap_or(String::default()))
+        .parse_filters(&log_level)
+        .parse_write_style(&args.log_style.clone().unwrap_or_default())
         .init();
 
+    let mut config = match &args.config {
+        Some(path) => RunConfig::load(path).unwrap_or_else(|_err| {
+            error!("{_err} \"{}\"", path.display());
+            process::exit(3);
+        }),
+        None => RunConfig::default(),
+    };
+    if let Some(start_offset) = args.start_offset {
+        config.output.start_offset = start_offset;
+    }
+    if args.json_dir.is_some() {
+        config.output.json_dir = args.json_dir.clone();
+    }
+    if let Some(explain) = args.explain {
+        config.output.explain = Some(TransactionId::from(explain));
+    }
+    config.output.quiet = args.quiet;
+
     tokio::runtime::Runtime::new().unwrap().block_on(async {
-        match File::open(&args.filename).await {
-            Ok(file) => {
-                let capacity = 0x1000;
-                let reader = tokio::io::BufReader::with_capacity(capacity, file);
-                let mut writer = tokio::io::stdout();
-                if let Err(_err) = process_csv(
-                    AccountHub::new(|_client_id| InMemoryLedger::connect()),
-                    reader,
-                    &mut writer,
-                )
-                .await
-                {
-                    error!("{_err}");
-                    process::exit(5);
+        match &config.ledger {
+            LedgerBackend::InMemory => {
+                let accounts = AccountHub::new(|_client_id| InMemoryLedger::connect());
+                run(config.configure(accounts), &config, &args).await
+            }
+            #[cfg(feature = "sled")]
+            LedgerBackend::Sled { path } => {
+                match sled::open(path) {
+                    Ok(db) => {
+                        let _ = SLED_DB.set(db);
+                    }
+                    Err(_err) => {
+                        error!("{_err} \"{}\"", path.display());
+                        process::exit(3);
+                    }
                 }
+                let accounts = AccountHub::new(|client_id| {
+                    let tree = SLED_DB.get()?.open_tree(client_id.to_string()).ok()?;
+                    Some(SledLedger::from_tree(tree))
+                });
+                run(config.configure(accounts), &config, &args).await
             }
-            Err(_err) => {
-                error!("{_err} \"{}\"", &args.filename);
-                process::exit(4);
+            #[cfg(not(feature = "sled"))]
+            LedgerBackend::Sled { .. } => {
+                error!("The sled ledger backend is not compiled in (enable the \"sled\" feature)");
+                process::exit(3);
             }
-        };
+        }
     });
 }
+
+async fn run<L>(accounts: AccountHub<L>, config: &RunConfig, args: &Args)
+where
+    L: Ledger + 'static,
+{
+    match File::open(&args.filename).await {
+        Ok(file) => {
+            let capacity = 0x1000;
+            let reader = tokio::io::BufReader::with_capacity(capacity, file);
+            let mut writer = tokio::io::stdout();
+            match process_csv_with(accounts, reader, &mut writer, &config.output).await {
+                Ok(report) => {
+                    if args.explain.is_some() && !config.output.quiet {
+                        for step in &report.explain {
+                            eprintln!("{step}");
+                        }
+                    }
+                    if args.stats && !config.output.quiet {
+                        eprintln!("{}", report.stats);
+                        eprintln!("end offset: {}", report.end_offset);
+                        if report.stats.dropped_responses > 0 {
+                            eprintln!("dropped responses: {}", report.stats.dropped_responses);
+                        }
+                        if let Some(deposit_stats) = &report.deposit_stats {
+                            eprintln!("deposits: {deposit_stats}");
+                        }
+                    }
+                }
+                Err(_err) => {
+                    error!("{_err}");
+                    process::exit(5);
+                }
+            }
+        }
+        Err(_err) => {
+            error!("{_err} \"{}\"", &args.filename);
+            process::exit(4);
+        }
+    };
+}
@@ -0,0 +1,166 @@
+use async_trait::async_trait;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+
+use crate::ledger::*;
+use crate::ClientId;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ReadOnlyLedgerError<E> {
+    /// a write was attempted on the read-only ledger
+    ReadOnly,
+    /// the wrapped ledger reported an error
+    Inner(E),
+}
+
+impl<E: Display> Display for ReadOnlyLedgerError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ReadOnlyLedgerError::ReadOnly => write!(f, "the ledger is read-only"),
+            ReadOnlyLedgerError::Inner(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl<E: Error> Error for ReadOnlyLedgerError<E> {}
+
+/// A 'Ledger' wrapper for inspection, guaranteeing that the wrapped ledger is not mutated:
+/// the reads are forwarded to it, while every write is refused with 'ReadOnlyLedgerError::ReadOnly'
+/// (so an Account using it answers the mutating actions with 'DbError').
+#[derive(Debug)]
+pub struct ReadOnlyLedger<L> {
+    inner: L,
+}
+
+impl<L: Ledger> ReadOnlyLedger<L> {
+    pub fn new(inner: L) -> Self {
+        ReadOnlyLedger { inner }
+    }
+
+    pub fn inner(&self) -> &L {
+        &self.inner
+    }
+
+    /// Gives back the wrapped ledger (writable again)
+    pub fn into_inner(self) -> L {
+        self.inner
+    }
+}
+
+#[async_trait]
+impl<L: Ledger> Ledger for ReadOnlyLedger<L> {
+    type Error = ReadOnlyLedgerError<L::Error>;
+
+    async fn contains(&self, key: TransactionId) -> Result<bool, Self::Error> {
+        self.inner
+            .contains(key)
+            .await
+            .map_err(ReadOnlyLedgerError::Inner)
+    }
+
+    async fn get(&self, key: TransactionId) -> Result<Option<TransactionState>, Self::Error> {
+        self.inner
+            .get(key)
+            .await
+            .map_err(ReadOnlyLedgerError::Inner)
+    }
+
+    async fn insert(
+        &mut self,
+        _key: TransactionId,
+        _state: TransactionState,
+    ) -> Result<(), Self::Error> {
+        Err(ReadOnlyLedgerError::ReadOnly)
+    }
+
+    async fn remove(&mut self, _key: TransactionId) -> Result<bool, Self::Error> {
+        Err(ReadOnlyLedgerError::ReadOnly)
+    }
+
+    async fn clear(&mut self) -> Result<bool, Self::Error> {
+        Err(ReadOnlyLedgerError::ReadOnly)
+    }
+
+    async fn clear_owned(&mut self, _owner: ClientId) -> Result<bool, Self::Error> {
+        Err(ReadOnlyLedgerError::ReadOnly)
+    }
+
+    /// closing is not a write, the wrapped ledger has to be torn down anyway
+    async fn close(&mut self) -> Result<(), Self::Error> {
+        self.inner.close().await.map_err(ReadOnlyLedgerError::Inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::*;
+    use crate::in_memory_ledger::InMemoryLedger;
+
+    #[tokio::test]
+    async fn read_only() {
+        let id = TransactionId::from(1);
+        let other = TransactionId::from(2);
+        let deposit = TransactionState::Deposit(Amount::ONE, TransactionInfo::default());
+        let mut inner = InMemoryLedger::connect().unwrap();
+        inner.insert(id, deposit).await.unwrap();
+
+        let mut ledger = ReadOnlyLedger::new(inner);
+
+        //reads pass through
+        assert!(ledger.contains(id).await.unwrap());
+        assert!(!ledger.contains(other).await.unwrap());
+        assert_eq!(ledger.get(id).await.unwrap(), Some(deposit));
+        assert_eq!(ledger.get(other).await.unwrap(), None);
+
+        //writes are refused
+        let disputed = TransactionState::DepositInDispute(
+            Amount::ONE,
+            TransactionInfo {
+                held: Amount::ONE,
+                ..TransactionInfo::default()
+            },
+        );
+        assert_eq!(
+            ledger.insert(id, disputed).await,
+            Err(ReadOnlyLedgerError::ReadOnly)
+        );
+        assert_eq!(
+            ledger.insert(other, deposit).await,
+            Err(ReadOnlyLedgerError::ReadOnly)
+        );
+        assert_eq!(ledger.remove(id).await, Err(ReadOnlyLedgerError::ReadOnly));
+        assert_eq!(ledger.clear().await, Err(ReadOnlyLedgerError::ReadOnly));
+        assert_eq!(
+            ledger.clear_owned(ClientId::from(1)).await,
+            Err(ReadOnlyLedgerError::ReadOnly)
+        );
+        assert!(ledger.apply_batch(vec![(other, deposit)]).await.is_err());
+        assert_eq!(ledger.close().await, Ok(()));
+
+        //nothing changed in the wrapped ledger
+        let inner = ledger.into_inner();
+        assert_eq!(inner.get(id).await.unwrap(), Some(deposit));
+        assert!(!inner.contains(other).await.unwrap());
+
+        //an account on it refuses the mutating actions
+        let mut account = Account::new(ReadOnlyLedger::new(inner));
+        assert_eq!(
+            account
+                .execute(Action::Transact(
+                    (other, Transaction::Deposit(Amount::ONE)),
+                    None
+                ))
+                .await,
+            Err(TransactionError::DbError)
+        );
+        assert_eq!(
+            account.execute(Action::Dispute(id)).await,
+            Err(TransactionError::DbError)
+        );
+        assert_eq!(account.transaction_state(id).await, Ok(Some(deposit)));
+        assert_eq!(account.total(), Amount::ZERO);
+        assert_eq!(account.held(), Amount::ZERO);
+    }
+}
@@ -0,0 +1,209 @@
+use async_trait::async_trait;
+use std::error::Error;
+use std::fmt;
+use std::fmt::Display;
+use std::path::Path;
+
+use crate::ledger::*;
+use crate::ClientId;
+
+#[derive(Debug)]
+pub enum SledLedgerError {
+    /// the embedded database reported an error
+    Db(sled::Error),
+    /// the stored value is not a valid transaction state
+    Corrupted,
+}
+
+impl Display for SledLedgerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SledLedgerError::Db(err) => write!(f, "sled ledger error: {err}"),
+            SledLedgerError::Corrupted => {
+                write!(f, "sled ledger error: corrupted transaction state")
+            }
+        }
+    }
+}
+
+impl Error for SledLedgerError {}
+
+impl From<sled::Error> for SledLedgerError {
+    fn from(err: sled::Error) -> Self {
+        SledLedgerError::Db(err)
+    }
+}
+
+/// A persistent implementation of 'Ledger' on the embedded 'sled' database.
+/// The transaction states are stored in a sled tree, keyed by the binary transaction id.
+/// Every insert is flushed to the disk before returning success.
+#[derive(Debug)]
+pub struct SledLedger {
+    tree: sled::Tree,
+}
+
+impl SledLedger {
+    /// Opens (or creates) the database at the given path
+    pub fn connect<P: AsRef<Path>>(path: P) -> Option<Self> {
+        sled::open(path)
+            .ok()
+            .map(|db| Self::from_tree((*db).clone()))
+    }
+
+    /// Uses an already opened tree (this way more ledgers can share a database with distinct trees)
+    pub fn from_tree(tree: sled::Tree) -> Self {
+        Self { tree }
+    }
+}
+
+#[async_trait]
+impl Ledger for SledLedger {
+    type Error = SledLedgerError;
+
+    async fn contains(&self, key: TransactionId) -> Result<bool, Self::Error> {
+        Ok(self.tree.contains_key(key.to_bytes())?)
+    }
+
+    async fn get(&self, key: TransactionId) -> Result<Option<TransactionState>, Self::Error> {
+        match self.tree.get(key.to_bytes())? {
+            Some(value) => TransactionState::from_bytes(&value)
+                .map(Some)
+                .ok_or(SledLedgerError::Corrupted),
+            None => Ok(None),
+        }
+    }
+
+    async fn insert(
+        &mut self,
+        key: TransactionId,
+        state: TransactionState,
+    ) -> Result<(), Self::Error> {
+        self.tree.insert(key.to_bytes(), state.to_bytes())?;
+        self.tree.flush_async().await?;
+        Ok(())
+    }
+
+    async fn remove(&mut self, key: TransactionId) -> Result<bool, Self::Error> {
+        self.tree.remove(key.to_bytes())?;
+        self.tree.flush_async().await?;
+        Ok(true)
+    }
+
+    async fn clear(&mut self) -> Result<bool, Self::Error> {
+        self.tree.clear()?;
+        self.tree.flush_async().await?;
+        Ok(true)
+    }
+
+    /// the owned keys are collected by a scan of the tree, then removed in one batch
+    async fn clear_owned(&mut self, owner: ClientId) -> Result<bool, Self::Error> {
+        let mut batch = sled::Batch::default();
+        for entry in self.tree.iter() {
+            let (key, value) = entry?;
+            let state = TransactionState::from_bytes(&value).ok_or(SledLedgerError::Corrupted)?;
+            if state.info().owner == Some(owner) {
+                batch.remove(key);
+            }
+        }
+        self.tree.apply_batch(batch)?;
+        self.tree.flush_async().await?;
+        Ok(true)
+    }
+
+    async fn close(&mut self) -> Result<(), Self::Error> {
+        self.tree.flush_async().await?;
+        Ok(())
+    }
+
+    /// sled batches are applied atomically
+    async fn apply_batch(
+        &mut self,
+        updates: Vec<(TransactionId, TransactionState)>,
+    ) -> Result<(), Self::Error> {
+        let mut batch = sled::Batch::default();
+        for (key, state) in updates {
+            batch.insert(&key.to_bytes(), state.to_bytes());
+        }
+        self.tree.apply_batch(batch)?;
+        self.tree.flush_async().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    /// a fresh database path, removed on drop
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> Self {
+            let path =
+                std::env::temp_dir().join(format!("accounter-sled-{}-{name}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            TempDir(path)
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[tokio::test]
+    async fn conformance() {
+        let dir = TempDir::new("conformance");
+        crate::ledger::conformance::run(&mut SledLedger::connect(&dir.0).unwrap()).await;
+    }
+
+    #[tokio::test]
+    async fn persistence() {
+        let dir = TempDir::new("persistence");
+        let deposit = TransactionState::Deposit(Amount::ONE, TransactionInfo::default());
+        let withdrawal = TransactionState::Withdrawal(Amount::MAX, TransactionInfo::default());
+        {
+            let mut ledger = SledLedger::connect(&dir.0).unwrap();
+            ledger
+                .insert(TransactionId::from(1), deposit)
+                .await
+                .unwrap();
+            ledger
+                .insert(TransactionId::from(2), withdrawal)
+                .await
+                .unwrap();
+            assert_eq!(
+                ledger.get(TransactionId::from(1)).await.unwrap(),
+                Some(deposit)
+            );
+        }
+
+        let ledger = SledLedger::connect(&dir.0).unwrap();
+        assert!(ledger.contains(TransactionId::from(1)).await.unwrap());
+        assert_eq!(
+            ledger.get(TransactionId::from(1)).await.unwrap(),
+            Some(deposit)
+        );
+        assert_eq!(
+            ledger.get(TransactionId::from(2)).await.unwrap(),
+            Some(withdrawal)
+        );
+        assert!(!ledger.contains(TransactionId::from(3)).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn corrupted_value() {
+        let dir = TempDir::new("corrupted");
+        let ledger = SledLedger::connect(&dir.0).unwrap();
+        ledger
+            .tree
+            .insert(TransactionId::from(1).to_bytes(), &[9u8; 9])
+            .unwrap();
+        assert!(matches!(
+            ledger.get(TransactionId::from(1)).await,
+            Err(SledLedgerError::Corrupted)
+        ));
+    }
+}
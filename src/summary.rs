@@ -0,0 +1,295 @@
+/// Comparison of the account summaries of two runs
+/// (e.g. to check that a refactor or a new ledger backend produces identical results).
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fmt::Display;
+use std::ops::Deref;
+
+use crate::{Account, Amount, ClientId, Ledger};
+
+/// The balances of an account at the end of a run (the columns of the summary output)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccountSummary {
+    pub client_id: ClientId,
+    pub available: Amount,
+    pub held: Amount,
+    pub total: Amount,
+    pub locked: bool,
+}
+
+impl AccountSummary {
+    pub fn new<L: Ledger>(client_id: ClientId, account: &Account<L>) -> Self {
+        AccountSummary {
+            client_id,
+            available: account.available(),
+            held: account.held(),
+            total: account.total(),
+            locked: account.is_locked(),
+        }
+    }
+
+    /// The summaries of the accounts returned by 'AccountHub::summarize'
+    pub fn of_accounts<L: Ledger>(accounts: &[(ClientId, Account<L>)]) -> Vec<Self> {
+        accounts
+            .iter()
+            .map(|(client_id, account)| AccountSummary::new(*client_id, account))
+            .collect()
+    }
+
+    /// The names of the columns having different values in the two summaries
+    pub fn differing_fields(&self, other: &AccountSummary) -> Vec<&'static str> {
+        [
+            ("available", self.available != other.available),
+            ("held", self.held != other.held),
+            ("total", self.total != other.total),
+            ("locked", self.locked != other.locked),
+        ]
+        .into_iter()
+        .filter_map(|(name, differs)| differs.then_some(name))
+        .collect()
+    }
+}
+
+/// The summaries of a run ordered by client id, so the summary of a client can be looked up quickly
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Summaries(Vec<AccountSummary>);
+
+impl Summaries {
+    /// The summaries of the accounts returned by 'AccountHub::summarize'
+    pub fn of_accounts<L: Ledger>(accounts: &[(ClientId, Account<L>)]) -> Self {
+        Summaries::from(AccountSummary::of_accounts(accounts))
+    }
+
+    /// The summary of the client (None if the client has no account)
+    pub fn get(&self, client_id: ClientId) -> Option<&AccountSummary> {
+        self.0
+            .binary_search_by_key(&client_id, |summary| summary.client_id)
+            .ok()
+            .map(|index| &self.0[index])
+    }
+
+    pub fn into_vec(self) -> Vec<AccountSummary> {
+        self.0
+    }
+}
+
+impl From<Vec<AccountSummary>> for Summaries {
+    /// Sorts the summaries by client id (keeping the order of the summaries of the same client)
+    fn from(mut summaries: Vec<AccountSummary>) -> Self {
+        summaries.sort_by_key(|summary| summary.client_id);
+        Summaries(summaries)
+    }
+}
+
+impl Deref for Summaries {
+    type Target = [AccountSummary];
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl IntoIterator for Summaries {
+    type Item = AccountSummary;
+    type IntoIter = std::vec::IntoIter<AccountSummary>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl<'a> IntoIterator for &'a Summaries {
+    type Item = &'a AccountSummary;
+    type IntoIter = std::slice::Iter<'a, AccountSummary>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.iter()
+    }
+}
+
+/// A difference of the summaries of a client between two runs
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SummaryDiff {
+    /// the client has an account only in the first run
+    OnlyInFirst(AccountSummary),
+    /// the client has an account only in the second run
+    OnlyInSecond(AccountSummary),
+    /// the client has an account in both runs, but with different balances
+    Changed {
+        first: AccountSummary,
+        second: AccountSummary,
+    },
+}
+
+impl SummaryDiff {
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            SummaryDiff::OnlyInFirst(summary) | SummaryDiff::OnlyInSecond(summary) => {
+                summary.client_id
+            }
+            SummaryDiff::Changed { first, .. } => first.client_id,
+        }
+    }
+}
+
+impl Display for SummaryDiff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SummaryDiff::OnlyInFirst(summary) => {
+                write!(f, "client {} only in the first run", summary.client_id)
+            }
+            SummaryDiff::OnlyInSecond(summary) => {
+                write!(f, "client {} only in the second run", summary.client_id)
+            }
+            SummaryDiff::Changed { first, second } => {
+                write!(f, "client {}:", first.client_id)?;
+                for field in first.differing_fields(second) {
+                    match field {
+                        "available" => {
+                            write!(f, " available {} != {}", first.available, second.available)?
+                        }
+                        "held" => write!(f, " held {} != {}", first.held, second.held)?,
+                        "total" => write!(f, " total {} != {}", first.total, second.total)?,
+                        _ => write!(f, " locked {} != {}", first.locked, second.locked)?,
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Compares the summaries of two runs client by client (the order of the summaries does not matter),
+/// returns the differences ordered by client id (empty if the runs produced identical results).
+pub fn diff_summaries(a: &[AccountSummary], b: &[AccountSummary]) -> Vec<SummaryDiff> {
+    let mut clients = BTreeMap::<ClientId, (Option<AccountSummary>, Option<AccountSummary>)>::new();
+    for summary in a {
+        clients.entry(summary.client_id).or_default().0 = Some(*summary);
+    }
+    for summary in b {
+        clients.entry(summary.client_id).or_default().1 = Some(*summary);
+    }
+    clients
+        .into_values()
+        .filter_map(|summaries| match summaries {
+            (Some(first), Some(second)) if first != second => {
+                Some(SummaryDiff::Changed { first, second })
+            }
+            (Some(first), None) => Some(SummaryDiff::OnlyInFirst(first)),
+            (None, Some(second)) => Some(SummaryDiff::OnlyInSecond(second)),
+            _ => None,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::in_memory_ledger::InMemoryLedger;
+    use crate::*;
+    use std::str::FromStr;
+
+    async fn run(input: &[(u16, &str)]) -> Vec<AccountSummary> {
+        let (response_sender, _response_receiver) = tokio::sync::mpsc::channel::<Response>(64);
+        let mut hub = AccountHub::new(|_client_id| InMemoryLedger::connect());
+        for (id, (client, amount)) in input.iter().enumerate() {
+            let action = Action::Transact(
+                (
+                    TransactionId::from(id as u32 + 1),
+                    Transaction::Deposit(Amount::from_str(amount).unwrap()),
+                ),
+                None,
+            );
+            hub.execute(ClientId::from(*client), action, &response_sender)
+                .await
+                .unwrap();
+        }
+        let _ = hub
+            .execute(
+                ClientId::from(2),
+                Action::Dispute(TransactionId::from(2)),
+                &response_sender,
+            )
+            .await;
+        AccountSummary::of_accounts(&hub.summarize().await)
+    }
+
+    #[tokio::test]
+    async fn diffs() {
+        let input = [(1, "1.5"), (2, "20"), (3, "3"), (2, "1")];
+        let summaries = run(&input).await;
+        assert_eq!(summaries.len(), 3);
+        assert!(diff_summaries(&summaries, &summaries).is_empty());
+        assert!(diff_summaries(&summaries, &run(&input).await).is_empty());
+
+        //the order does not matter
+        let mut reversed = summaries.clone();
+        reversed.reverse();
+        assert!(diff_summaries(&summaries, &reversed).is_empty());
+
+        let mut altered = summaries.clone();
+        altered[1].available = Amount::ZERO;
+        altered[1].locked = true;
+        let diff = diff_summaries(&summaries, &altered);
+        assert_eq!(
+            diff,
+            vec![SummaryDiff::Changed {
+                first: summaries[1],
+                second: altered[1]
+            }]
+        );
+        assert_eq!(diff[0].client_id(), ClientId::from(2));
+        assert_eq!(
+            summaries[1].differing_fields(&altered[1]),
+            vec!["available", "locked"]
+        );
+        assert_eq!(
+            diff[0].to_string(),
+            "client 2: available 1 != 0 locked false != true"
+        );
+
+        let other = run(&[(1, "1.5"), (2, "20"), (4, "4"), (2, "1")]).await;
+        let diff = diff_summaries(&summaries, &other);
+        assert_eq!(
+            diff.iter().map(|diff| diff.to_string()).collect::<Vec<_>>(),
+            vec![
+                "client 3 only in the first run",
+                "client 4 only in the second run"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn lookup() {
+        let summaries = Summaries::from(run(&[(7, "7"), (2, "20"), (3, "3"), (2, "1")]).await);
+        assert_eq!(summaries.len(), 3);
+        let summary = summaries.get(ClientId::from(2)).unwrap();
+        assert_eq!(summary.client_id, ClientId::from(2));
+        assert_eq!(summary.available, Amount::ONE);
+        assert_eq!(summary.held, Amount::from_str("20").unwrap());
+        assert_eq!(
+            summaries.get(ClientId::from(7)).unwrap().total,
+            Amount::from_str("7").unwrap()
+        );
+        assert_eq!(summaries.get(ClientId::from(1)), None);
+        assert_eq!(summaries.get(ClientId::from(4)), None);
+        assert_eq!(summaries.get(ClientId::MAX), None);
+        assert_eq!(Summaries::default().get(ClientId::MIN), None);
+
+        //ordered by client id, regardless of the order of construction
+        let mut reversed = summaries.clone().into_vec();
+        reversed.reverse();
+        assert_eq!(Summaries::from(reversed), summaries);
+        assert_eq!(
+            (&summaries)
+                .into_iter()
+                .map(|summary| summary.client_id)
+                .collect::<Vec<_>>(),
+            vec![ClientId::from(2), ClientId::from(3), ClientId::from(7)]
+        );
+        assert!(diff_summaries(&summaries, &summaries).is_empty());
+
+        let hub = AccountHub::new(|_client_id| InMemoryLedger::connect());
+        assert!(hub.summaries().await.into_iter().next().is_none());
+    }
+}
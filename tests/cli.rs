@@ -0,0 +1,70 @@
+use std::path::PathBuf;
+use std::process::{Command, Output};
+
+/// a transactions file with mostly refused actions, removed on drop
+struct InputFile(PathBuf);
+
+impl InputFile {
+    fn new(name: &str) -> Self {
+        let path =
+            std::env::temp_dir().join(format!("accounter-cli-{}-{name}.csv", std::process::id()));
+        let mut csv = String::from("type, client, tx, amount\ndeposit, 1, 1, 1.0\n");
+        for tx in 2..100 {
+            csv.push_str(&format!("withdrawal, 1, {tx}, 5\n"));
+            csv.push_str(&format!("dispute, 1, {}\n", tx + 1000));
+            csv.push_str("malformed line\n");
+        }
+        std::fs::write(&path, csv).unwrap();
+        InputFile(path)
+    }
+}
+
+impl Drop for InputFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
+
+fn run(input: &InputFile, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_accounter"))
+        .arg(&input.0)
+        .args(args)
+        .env_remove("ACCOUNTS_LOG_LEVEL")
+        .output()
+        .unwrap()
+}
+
+const SUMMARY: &str = "client,available,held,total,locked\n1, 1, 0, 1, false\n";
+
+#[test]
+fn logging_on_stderr() {
+    let input = InputFile::new("logging");
+    let output = run(&input, &["--log-level", "trace", "--stats"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), SUMMARY);
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn quiet_mode() {
+    let input = InputFile::new("quiet");
+    let output = run(&input, &["--log-level", "trace", "--stats", "--quiet"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), SUMMARY);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}
+
+#[test]
+fn explain_transaction() {
+    let input = InputFile::new("explain");
+    let output = run(&input, &["--explain", "1"]);
+    assert!(output.status.success());
+    assert_eq!(String::from_utf8_lossy(&output.stdout), SUMMARY);
+    assert_eq!(
+        String::from_utf8_lossy(&output.stderr),
+        "line 2 \"deposit, 1, 1, 1.0\": ok, tx: Deposit(1), available: 1, held: 0, total: 1, locked: false\n"
+    );
+
+    let output = run(&input, &["--explain", "1", "--quiet"]);
+    assert_eq!(String::from_utf8_lossy(&output.stderr), "");
+}